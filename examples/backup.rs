@@ -15,19 +15,30 @@ struct Args {
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let client = TasmotaClient::connect(
-        &args.hostname,
-        args.port,
-        Some((&args.username, &args.password)),
-    )
-    .await?;
+    let client = TasmotaClient::builder(&args.hostname, args.port)
+        .credentials(&args.username, &args.password)
+        .connect()
+        .await?;
     let file = client
         .download_config(&args.device, &args.device_password)
         .await?;
 
     println!("downloaded {} with hash {}", file.name, HexFmt(file.md5));
-    if let Err(e) = std::fs::write(&file.name, file.data) {
+    if let Err(e) = std::fs::write(&file.name, &file.data) {
         eprintln!("Error while saving {}: {:#}", file.name, e);
     }
+
+    match file.settings() {
+        Ok(settings) => {
+            let json_name = format!("{}.json", file.name);
+            let json = serde_json::to_string_pretty(&settings).unwrap();
+            if let Err(e) = std::fs::write(&json_name, json) {
+                eprintln!("Error while saving {}: {:#}", json_name, e);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error while decoding settings: {:#}", e);
+        }
+    }
     Ok(())
 }
@@ -15,23 +15,35 @@ struct Args {
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let client = TasmotaClient::connect(
-        &args.hostname,
-        args.port,
-        Some((&args.username, &args.password)),
-    )
-    .await?;
+    let client = TasmotaClient::builder(&args.hostname, args.port)
+        .credentials(&args.username, &args.password)
+        .connect()
+        .await?;
 
     let mut discovery = pin!(client.devices());
     while let Some(update) = discovery.next().await {
         match update {
             DeviceUpdate::Added(device) => {
-                let (ip, name) = join!(client.device_ip(&device), client.device_name(&device));
-                println!("discovered {}({device}) with ip {}", name?, ip?);
+                let topic = &device.topic;
+                // older firmware doesn't publish discovery info, ask the device instead
+                let (ip, name) = match (device.ip, device.device_name) {
+                    (Some(ip), Some(name)) => (ip, name),
+                    _ => {
+                        let (ip, name) = join!(client.device_ip(topic), client.device_name(topic));
+                        (ip?, name?)
+                    }
+                };
+                println!("discovered {name}({topic}) with ip {ip}");
             }
             DeviceUpdate::Removed(device) => {
                 println!("{device} has gone offline");
             }
+            DeviceUpdate::Booted(boot) => {
+                if let Some(reason) = boot.restart_reason.filter(|reason| reason.is_crash()) {
+                    println!("{} crashed: {reason:?}", boot.device);
+                }
+            }
+            _ => {}
         }
     }
     Ok(())
@@ -0,0 +1,49 @@
+use clap::Parser;
+use std::path::PathBuf;
+use std::pin::pin;
+pub use tasmota_mqtt_client::{FirmwareUpdate, Result, TasmotaClient};
+use tokio_stream::StreamExt;
+
+#[derive(Debug, Parser)]
+struct Args {
+    hostname: String,
+    port: u16,
+    username: String,
+    password: String,
+    device: String,
+    device_password: String,
+    file: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let client = TasmotaClient::builder(&args.hostname, args.port)
+        .credentials(&args.username, &args.password)
+        .connect()
+        .await?;
+
+    let firmware = match std::fs::read(&args.file) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error while reading {}: {:#}", args.file.display(), e);
+            return Ok(());
+        }
+    };
+
+    let mut update = pin!(client.upload_firmware(&args.device, &args.device_password, firmware));
+    while let Some(update) = update.next().await {
+        match update? {
+            FirmwareUpdate::Progress(progress) => {
+                println!("uploaded {}/{} bytes", progress.sent, progress.total);
+            }
+            FirmwareUpdate::Restarting => {
+                println!("waiting for {} to restart", args.device);
+            }
+            FirmwareUpdate::Online { previous, version } => {
+                println!("updated {} from {previous} to {version}", args.device);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use clap::Parser;
+use std::path::PathBuf;
+pub use tasmota_mqtt_client::{DownloadedFile, Result, TasmotaClient};
+
+#[derive(Debug, Parser)]
+struct Args {
+    hostname: String,
+    port: u16,
+    username: String,
+    password: String,
+    device: String,
+    device_password: String,
+    file: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let client = TasmotaClient::builder(&args.hostname, args.port)
+        .credentials(&args.username, &args.password)
+        .connect()
+        .await?;
+
+    let data = match std::fs::read(&args.file) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error while reading {}: {:#}", args.file.display(), e);
+            return Ok(());
+        }
+    };
+    let name = args
+        .file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file = DownloadedFile::new(name, data);
+
+    client
+        .upload_config(&args.device, &args.device_password, &file)
+        .await?;
+    println!("restored {} to {}", file.name, args.device);
+    Ok(())
+}
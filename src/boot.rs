@@ -0,0 +1,243 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::net::IpAddr;
+use tracing::debug;
+
+/// Information a device publishes to `tele/<topic>/INFO1` through `INFO3` after booting
+///
+/// See also [`DeviceUpdate::Booted`](crate::DeviceUpdate::Booted) and [`TasmotaClient::boot_info`](crate::TasmotaClient::boot_info).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootInfo {
+    /// Topic of the device
+    pub device: String,
+    /// Name of the configured module or template
+    pub module: Option<String>,
+    pub firmware_version: Option<String>,
+    pub fallback_topic: Option<String>,
+    pub group_topic: Option<String>,
+    pub hostname: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub restart_reason: Option<RestartReason>,
+    pub boot_count: Option<u32>,
+}
+
+/// Why a device restarted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestartReason {
+    PowerOn,
+    /// Reset by the hardware or software watchdog
+    Watchdog,
+    /// Reset after a crash
+    Exception,
+    /// Restarted by a command, firmware update or configuration change
+    Software,
+    DeepSleepWake,
+    /// Reset through the reset pin
+    External,
+    /// Any other reason, as reported by the device
+    Other(String),
+}
+
+impl RestartReason {
+    fn parse(reason: &str) -> Self {
+        let lower = reason.to_ascii_lowercase();
+        // esp32 firmware reports reasons like `RTC Watch dog Reset CPU`
+        if lower.contains("watchdog") || lower.contains("watch dog") || lower.contains("wdt") {
+            RestartReason::Watchdog
+        } else if lower.contains("exception") || lower.contains("panic") {
+            RestartReason::Exception
+        } else if lower.contains("power on") {
+            RestartReason::PowerOn
+        } else if lower.starts_with("software") {
+            RestartReason::Software
+        } else if lower.contains("deep-sleep") || lower.contains("deep sleep") {
+            RestartReason::DeepSleepWake
+        } else if lower.starts_with("external") {
+            RestartReason::External
+        } else {
+            RestartReason::Other(reason.into())
+        }
+    }
+
+    /// Whether the device restarted because it crashed or hung
+    pub fn is_crash(&self) -> bool {
+        matches!(self, RestartReason::Watchdog | RestartReason::Exception)
+    }
+}
+
+/// The fields of all three info messages
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct InfoMessage {
+    module: Option<String>,
+    version: Option<String>,
+    fallback_topic: Option<String>,
+    group_topic: Option<String>,
+    hostname: Option<String>,
+    #[serde(rename = "IPAddress")]
+    ip_address: Option<IpAddr>,
+    /// A string, or an object with details for crashes
+    restart_reason: Option<Value>,
+    boot_count: Option<u32>,
+}
+
+impl BootInfo {
+    pub(crate) fn new(device: &str) -> Self {
+        BootInfo {
+            device: device.into(),
+            ..BootInfo::default()
+        }
+    }
+
+    /// Merge an `INFO<n>` message into the boot info, returning true once the last message has been received
+    pub(crate) fn update(&mut self, name: &str, payload: &[u8]) -> bool {
+        let Ok(mut fields) = serde_json::from_slice::<Map<String, Value>>(payload) else {
+            debug!(device = self.device, name, "failed to parse boot info");
+            return false;
+        };
+        // newer firmware wraps the fields in an `Info<n>` object
+        if let Some(Value::Object(inner)) = fields.remove(&format!("Info{}", &name[4..])) {
+            fields = inner;
+        }
+        let info = match InfoMessage::deserialize(Value::Object(fields)) {
+            Ok(info) => info,
+            Err(e) => {
+                debug!(device = self.device, name, error = ?e, "failed to parse boot info");
+                return false;
+            }
+        };
+
+        self.module = info.module.or(self.module.take());
+        self.firmware_version = info.version.or(self.firmware_version.take());
+        self.fallback_topic = info.fallback_topic.or(self.fallback_topic.take());
+        self.group_topic = info.group_topic.or(self.group_topic.take());
+        self.hostname = info.hostname.or(self.hostname.take());
+        self.ip = info.ip_address.or(self.ip);
+        self.boot_count = info.boot_count.or(self.boot_count);
+        let reason = match &info.restart_reason {
+            Some(Value::String(reason)) => Some(reason.as_str()),
+            Some(Value::Object(details)) => details.get("Reason").and_then(Value::as_str),
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            self.restart_reason = Some(RestartReason::parse(reason));
+        }
+        name == "INFO3"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restart_reasons() {
+        // esp8266
+        assert_eq!(RestartReason::parse("Power On"), RestartReason::PowerOn);
+        assert_eq!(
+            RestartReason::parse("Hardware Watchdog"),
+            RestartReason::Watchdog
+        );
+        assert_eq!(
+            RestartReason::parse("Software Watchdog"),
+            RestartReason::Watchdog
+        );
+        assert_eq!(RestartReason::parse("Exception"), RestartReason::Exception);
+        assert_eq!(
+            RestartReason::parse("Software/System restart"),
+            RestartReason::Software
+        );
+        assert_eq!(
+            RestartReason::parse("Deep-Sleep Wake"),
+            RestartReason::DeepSleepWake
+        );
+        assert_eq!(
+            RestartReason::parse("External System"),
+            RestartReason::External
+        );
+        // esp32
+        assert_eq!(
+            RestartReason::parse("Vbat power on reset"),
+            RestartReason::PowerOn
+        );
+        assert_eq!(
+            RestartReason::parse("Software reset CPU"),
+            RestartReason::Software
+        );
+        assert_eq!(
+            RestartReason::parse("RTC Watch dog Reset CPU"),
+            RestartReason::Watchdog
+        );
+        assert_eq!(
+            RestartReason::parse("Timer Group0 Watch dog reset digital core"),
+            RestartReason::Watchdog
+        );
+        assert_eq!(
+            RestartReason::parse("Deep Sleep reset digital core"),
+            RestartReason::DeepSleepWake
+        );
+        assert_eq!(
+            RestartReason::parse("Brownout"),
+            RestartReason::Other("Brownout".into())
+        );
+        assert!(RestartReason::parse("RTC Watch dog Reset CPU").is_crash());
+    }
+
+    #[test]
+    fn update_bare_messages() {
+        let mut info = BootInfo::new("lamp");
+        assert!(!info.update(
+            "INFO1",
+            br#"{"Module":"Sonoff Basic","Version":"9.1.0(tasmota)","FallbackTopic":"cmnd/DVES_1234_fb/","GroupTopic":"cmnd/tasmotas/"}"#
+        ));
+        assert!(!info.update(
+            "INFO2",
+            br#"{"WebServerMode":"Admin","Hostname":"lamp-1234","IPAddress":"192.168.1.20"}"#
+        ));
+        assert!(info.update(
+            "INFO3",
+            br#"{"RestartReason":"Software Watchdog","BootCount":12}"#
+        ));
+        assert_eq!(
+            info,
+            BootInfo {
+                device: "lamp".into(),
+                module: Some("Sonoff Basic".into()),
+                firmware_version: Some("9.1.0(tasmota)".into()),
+                fallback_topic: Some("cmnd/DVES_1234_fb/".into()),
+                group_topic: Some("cmnd/tasmotas/".into()),
+                hostname: Some("lamp-1234".into()),
+                ip: Some("192.168.1.20".parse().unwrap()),
+                restart_reason: Some(RestartReason::Watchdog),
+                boot_count: Some(12),
+            }
+        );
+    }
+
+    #[test]
+    fn update_wrapped_messages() {
+        let mut info = BootInfo::new("plug");
+        assert!(!info.update(
+            "INFO1",
+            br#"{"Info1":{"Module":"ESP32-DevKit","Version":"13.1.0(tasmota32)","FallbackTopic":"cmnd/plug_fb/","GroupTopic":"cmnd/tasmotas/"}}"#
+        ));
+        assert!(!info.update(
+            "INFO2",
+            br#"{"Info2":{"WebServerMode":"Admin","Hostname":"plug","IPAddress":"10.0.0.5"}}"#
+        ));
+        assert!(info.update(
+            "INFO3",
+            br#"{"Info3":{"RestartReason":{"Reason":"Exception","Exception":28,"Epc":["40104a2b"]},"BootCount":3}}"#
+        ));
+        assert_eq!(info.module.as_deref(), Some("ESP32-DevKit"));
+        assert_eq!(info.firmware_version.as_deref(), Some("13.1.0(tasmota32)"));
+        assert_eq!(info.hostname.as_deref(), Some("plug"));
+        assert_eq!(info.ip, Some("10.0.0.5".parse().unwrap()));
+        assert_eq!(info.restart_reason, Some(RestartReason::Exception));
+        assert_eq!(info.boot_count, Some(3));
+
+        // invalid messages leave the info unchanged
+        assert!(!info.update("INFO3", b"Restarting"));
+        assert_eq!(info.boot_count, Some(3));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{Result, TasmotaClient, TopicScheme};
+use rumqttc::{MqttOptions, TlsConfiguration, Transport};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::process;
+use std::time::Duration;
+
+/// Default timeout for commands
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Default time for a device to come back online after a restart
+pub(crate) const DEFAULT_RESTART_TIMEOUT: Duration = Duration::from_secs(60);
+/// Default time for a device to acknowledge a chunk of a file upload
+pub(crate) const DEFAULT_UPLOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builder for configuring the connection of a [`TasmotaClient`]
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use std::time::Duration;
+/// # use tasmota_mqtt_client::{Result, TasmotaClient};
+/// # #[tokio::main]
+/// # async fn main() -> Result<()> {
+/// let client = TasmotaClient::builder("mqtt.example.com", 1883)
+///     .client_id("backup-job")
+///     .credentials("mqtt_username", "mqtt_password")
+///     .timeout(Duration::from_secs(5))
+///     .connect()
+///     .await?;
+/// #   Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TasmotaClientBuilder {
+    host: String,
+    port: u16,
+    client_id: Option<String>,
+    credentials: Option<(String, String)>,
+    keep_alive: Option<Duration>,
+    clean_session: bool,
+    tls: Option<TlsConfiguration>,
+    timeout: Duration,
+    restart_timeout: Duration,
+    upload_timeout: Duration,
+    topic_scheme: TopicScheme,
+}
+
+impl TasmotaClientBuilder {
+    pub(crate) fn new(host: impl Into<String>, port: u16) -> Self {
+        TasmotaClientBuilder {
+            host: host.into(),
+            port,
+            client_id: None,
+            credentials: None,
+            keep_alive: None,
+            clean_session: true,
+            tls: None,
+            timeout: DEFAULT_TIMEOUT,
+            restart_timeout: DEFAULT_RESTART_TIMEOUT,
+            upload_timeout: DEFAULT_UPLOAD_TIMEOUT,
+            topic_scheme: TopicScheme::default(),
+        }
+    }
+
+    /// Set the client id used to connect to the MQTT server
+    ///
+    /// The broker disconnects existing clients with the same id, so every running client needs a unique id.
+    /// By default a random client id is used.
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = Some(client_id.into());
+        self
+    }
+
+    /// Set the username and password for the MQTT server
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Set the keep-alive interval of the MQTT connection
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Set whether the MQTT server should discard the session when the client disconnects
+    ///
+    /// Defaults to `true`.
+    pub fn clean_session(mut self, clean_session: bool) -> Self {
+        self.clean_session = clean_session;
+        self
+    }
+
+    /// Connect to the MQTT server over TLS
+    pub fn tls(mut self, tls: TlsConfiguration) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Set the timeout used for one-shot commands
+    ///
+    /// The default timeout is 1 second.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the time a device gets to come back online after a restart
+    ///
+    /// The default is 60 seconds.
+    pub fn restart_timeout(mut self, restart_timeout: Duration) -> Self {
+        self.restart_timeout = restart_timeout;
+        self
+    }
+
+    /// Set the time a device gets to acknowledge every chunk of a config or firmware upload
+    ///
+    /// The default is 10 seconds.
+    pub fn upload_timeout(mut self, upload_timeout: Duration) -> Self {
+        self.upload_timeout = upload_timeout;
+        self
+    }
+
+    /// Set the topic layout for devices that don't publish their own layout through tasmota discovery
+    pub fn topic_scheme(mut self, topic_scheme: TopicScheme) -> Self {
+        self.topic_scheme = topic_scheme;
+        self
+    }
+
+    /// Connect to the MQTT server
+    pub async fn connect(self) -> Result<TasmotaClient> {
+        let client_id = self.client_id.unwrap_or_else(random_client_id);
+        let mut options = MqttOptions::new(client_id, self.host, self.port);
+        if let Some((username, password)) = self.credentials {
+            options.set_credentials(username, password);
+        }
+        if let Some(keep_alive) = self.keep_alive {
+            options.set_keep_alive(keep_alive);
+        }
+        options.set_clean_session(self.clean_session);
+        if let Some(tls) = self.tls {
+            options.set_transport(Transport::Tls(tls));
+        }
+
+        let mut client =
+            TasmotaClient::connect_with(options, self.topic_scheme, self.timeout).await?;
+        client.set_restart_timeout(self.restart_timeout);
+        client.set_upload_timeout(self.upload_timeout);
+        Ok(client)
+    }
+}
+
+fn random_client_id() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(process::id());
+    format!("tasmota-client-{:08x}", hasher.finish() as u32)
+}
@@ -0,0 +1,618 @@
+//! Typed versions of common tasmota commands, for use with [`TasmotaClient::execute`](crate::TasmotaClient::execute)
+
+use serde::de::{DeserializeOwned, Error as _, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+
+/// A command that can be sent to a device
+pub trait TasmotaCommand {
+    /// Type of the reply sent by the device
+    type Response: DeserializeOwned;
+
+    /// Name of the command, as used in the command topic
+    fn name(&self) -> Cow<'_, str>;
+
+    /// Payload sent with the command, an empty payload queries the current value for most commands
+    fn payload(&self) -> Cow<'_, str>;
+
+    /// Topic the reply is published to, relative to `stat/{device}/`
+    fn reply_topic(&self) -> Cow<'_, str> {
+        "RESULT".into()
+    }
+
+    /// Top level key in the reply that identifies it as belonging to this command
+    fn reply_key(&self) -> Cow<'_, str> {
+        self.name()
+    }
+
+    /// Whether the device sends a reply for this command
+    ///
+    /// For commands without reply, the response is deserialized from `null`.
+    fn expects_reply(&self) -> bool {
+        true
+    }
+}
+
+/// Deserialize the value from a reply with a single top level key
+///
+/// This is used for replies where the key contains the index of the relay, rule or timer.
+pub(crate) fn single_value<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    struct SingleValueVisitor<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for SingleValueVisitor<T> {
+        type Value = T;
+
+        fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+            formatter.write_str("an object with a single key")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<T, A::Error> {
+            let Some((_, value)) = map.next_entry::<String, T>()? else {
+                return Err(A::Error::custom("empty reply"));
+            };
+            while map.next_entry::<String, Value>()?.is_some() {}
+            Ok(value)
+        }
+    }
+
+    deserializer.deserialize_map(SingleValueVisitor(PhantomData))
+}
+
+/// Deserialize the "ON" and "OFF" values tasmota uses for boolean states
+pub(crate) fn on_off<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    match <Cow<str>>::deserialize(deserializer)?.as_ref() {
+        "ON" | "on" | "1" => Ok(true),
+        "OFF" | "off" | "0" => Ok(false),
+        value => Err(D::Error::custom(format!("invalid state {value}"))),
+    }
+}
+
+/// The state of a relay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    On,
+    Off,
+}
+
+impl<'de> Deserialize<'de> for PowerState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(if on_off(deserializer)? {
+            PowerState::On
+        } else {
+            PowerState::Off
+        })
+    }
+}
+
+impl Display for PowerState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PowerState::On => f.write_str("ON"),
+            PowerState::Off => f.write_str("OFF"),
+        }
+    }
+}
+
+/// Action to perform on a relay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    On,
+    Off,
+    Toggle,
+    Blink,
+    BlinkOff,
+}
+
+impl From<PowerState> for PowerAction {
+    fn from(state: PowerState) -> Self {
+        match state {
+            PowerState::On => PowerAction::On,
+            PowerState::Off => PowerAction::Off,
+        }
+    }
+}
+
+impl PowerAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PowerAction::On => "ON",
+            PowerAction::Off => "OFF",
+            PowerAction::Toggle => "TOGGLE",
+            PowerAction::Blink => "BLINK",
+            PowerAction::BlinkOff => "BLINKOFF",
+        }
+    }
+}
+
+/// Get or change the state of a relay
+#[derive(Debug, Clone)]
+pub struct Power {
+    /// Index of the relay, starting at 1
+    pub index: u8,
+    pub action: Option<PowerAction>,
+}
+
+impl Power {
+    /// Get the current state of a relay
+    pub fn get(index: u8) -> Self {
+        Power {
+            index,
+            action: None,
+        }
+    }
+
+    /// Change the state of a relay
+    pub fn set(index: u8, action: impl Into<PowerAction>) -> Self {
+        Power {
+            index,
+            action: Some(action.into()),
+        }
+    }
+}
+
+/// Reply to [`Power`]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct PowerResponse(#[serde(deserialize_with = "single_value")] pub PowerState);
+
+impl TasmotaCommand for Power {
+    type Response = PowerResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        format!("Power{}", self.index).into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.action
+            .map(|action| action.as_str())
+            .unwrap_or_default()
+            .into()
+    }
+}
+
+/// Request one section of the device status
+///
+/// Use [`TasmotaClient::status`](crate::TasmotaClient::status) to request all sections at once.
+#[derive(Debug, Clone)]
+pub struct Status(pub u8);
+
+impl Status {
+    fn section_key(&self) -> &'static str {
+        match self.0 {
+            1 => "StatusPRM",
+            2 => "StatusFWR",
+            3 => "StatusLOG",
+            4 => "StatusMEM",
+            5 => "StatusNET",
+            6 => "StatusMQT",
+            7 => "StatusTIM",
+            8 | 10 => "StatusSNS",
+            9 => "StatusPTH",
+            11 => "StatusSTS",
+            12 => "StatusSTK",
+            _ => "Status",
+        }
+    }
+}
+
+/// Reply to [`Status`], the content of the requested section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct StatusResponse(#[serde(deserialize_with = "single_value")] pub Value);
+
+impl TasmotaCommand for Status {
+    type Response = StatusResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "Status".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0.to_string().into()
+    }
+
+    fn reply_topic(&self) -> Cow<'_, str> {
+        format!("STATUS{}", self.0).into()
+    }
+
+    fn reply_key(&self) -> Cow<'_, str> {
+        self.section_key().into()
+    }
+}
+
+/// Get or set the brightness of a light, from 0 to 100
+#[derive(Debug, Clone)]
+pub struct Dimmer(pub Option<u8>);
+
+/// Reply to [`Dimmer`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct DimmerResponse {
+    #[serde(rename = "Dimmer")]
+    pub dimmer: u8,
+}
+
+impl TasmotaCommand for Dimmer {
+    type Response = DimmerResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "Dimmer".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+            .into()
+    }
+}
+
+/// Get or set the color of a light
+///
+/// The color is passed in any format supported by tasmota, like `FF8000` or `255,128,0`.
+#[derive(Debug, Clone)]
+pub struct Color(pub Option<String>);
+
+/// Reply to [`Color`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ColorResponse {
+    #[serde(rename = "Color")]
+    pub color: String,
+}
+
+impl TasmotaCommand for Color {
+    type Response = ColorResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "Color".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0.as_deref().unwrap_or_default().into()
+    }
+}
+
+/// Get or set the color temperature of a light in mireds, from 153 to 500
+#[derive(Debug, Clone)]
+pub struct Ct(pub Option<u16>);
+
+/// Reply to [`Ct`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct CtResponse {
+    #[serde(rename = "CT")]
+    pub ct: u16,
+}
+
+impl TasmotaCommand for Ct {
+    type Response = CtResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "CT".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+            .into()
+    }
+}
+
+/// Execute multiple commands in sequence
+///
+/// The device doesn't reply to the backlog itself.
+#[derive(Debug, Clone)]
+pub struct Backlog(pub Vec<String>);
+
+impl TasmotaCommand for Backlog {
+    type Response = ();
+
+    fn name(&self) -> Cow<'_, str> {
+        "Backlog".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0.join("; ").into()
+    }
+
+    fn expects_reply(&self) -> bool {
+        false
+    }
+}
+
+/// Get or set a `SetOption`
+#[derive(Debug, Clone)]
+pub struct SetOption {
+    pub option: u8,
+    pub value: Option<u8>,
+}
+
+/// Reply to [`SetOption`], flag options are returned as 0 or 1
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct SetOptionResponse(#[serde(deserialize_with = "single_value")] pub SetOptionValue);
+
+/// The value of a `SetOption`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetOptionValue(pub u8);
+
+impl<'de> Deserialize<'de> for SetOptionValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::Number(number) => number
+                .as_u64()
+                .and_then(|value| u8::try_from(value).ok())
+                .map(SetOptionValue)
+                .ok_or_else(|| D::Error::custom(format!("invalid option value {number}"))),
+            Value::String(value) => on_off(Value::String(value))
+                .map(|value| SetOptionValue(value as u8))
+                .map_err(D::Error::custom),
+            value => Err(D::Error::custom(format!("invalid option value {value}"))),
+        }
+    }
+}
+
+impl TasmotaCommand for SetOption {
+    type Response = SetOptionResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        format!("SetOption{}", self.option).into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.value
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+            .into()
+    }
+}
+
+/// Get or change a rule set
+///
+/// The payload can be the rule text or one of the control values like `1` or `0` to enable or disable the rule set.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// Index of the rule set, from 1 to 3
+    pub index: u8,
+    pub payload: Option<String>,
+}
+
+/// Reply to [`Rule`]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct RuleResponse(#[serde(deserialize_with = "single_value")] pub RuleInfo);
+
+/// State of a rule set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RuleInfo {
+    #[serde(deserialize_with = "on_off")]
+    pub state: bool,
+    #[serde(deserialize_with = "on_off")]
+    pub once: bool,
+    #[serde(deserialize_with = "on_off")]
+    pub stop_on_error: bool,
+    pub length: usize,
+    pub free: usize,
+    pub rules: String,
+}
+
+impl TasmotaCommand for Rule {
+    type Response = RuleResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        format!("Rule{}", self.index).into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.payload.as_deref().unwrap_or_default().into()
+    }
+}
+
+/// Get or change a timer
+#[derive(Debug, Clone)]
+pub struct Timer {
+    /// Index of the timer, from 1 to 16
+    pub index: u8,
+    /// Json timer definition, or `0` to disarm the timer
+    pub payload: Option<String>,
+}
+
+/// Reply to [`Timer`]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct TimerResponse(#[serde(deserialize_with = "single_value")] pub TimerInfo);
+
+/// Timer definition as returned by the device
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TimerInfo {
+    pub enable: u8,
+    /// Missing on firmware built without sunrise and sunset support, which only has fixed times
+    #[serde(default)]
+    pub mode: u8,
+    /// Time as `HH:MM`, or an offset for sunrise and sunset modes
+    pub time: String,
+    pub window: u8,
+    /// Active days as `SMTWTFS`, with `-` for inactive days
+    pub days: String,
+    pub repeat: u8,
+    pub output: u8,
+    pub action: u8,
+}
+
+impl TasmotaCommand for Timer {
+    type Response = TimerResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        format!("Timer{}", self.index).into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.payload.as_deref().unwrap_or_default().into()
+    }
+}
+
+/// Get or set the base module of the device
+#[derive(Debug, Clone)]
+pub struct Module(pub Option<u8>);
+
+/// Reply to [`Module`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModuleResponse {
+    /// Module id and name
+    #[serde(rename = "Module")]
+    pub module: BTreeMap<String, String>,
+}
+
+impl TasmotaCommand for Module {
+    type Response = ModuleResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "Module".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+            .into()
+    }
+}
+
+/// Get or set the device template
+#[derive(Debug, Clone)]
+pub struct Template(pub Option<String>);
+
+/// Reply to [`Template`]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct TemplateResponse {
+    pub name: String,
+    pub gpio: Vec<u16>,
+    pub flag: u16,
+    pub base: u8,
+}
+
+impl TasmotaCommand for Template {
+    type Response = TemplateResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "Template".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0.as_deref().unwrap_or_default().into()
+    }
+
+    fn reply_key(&self) -> Cow<'_, str> {
+        "NAME".into()
+    }
+}
+
+/// Get or set the interval in seconds between telemetry messages
+#[derive(Debug, Clone)]
+pub struct TelePeriod(pub Option<u16>);
+
+/// Reply to [`TelePeriod`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelePeriodResponse {
+    #[serde(rename = "TelePeriod")]
+    pub tele_period: u16,
+}
+
+impl TasmotaCommand for TelePeriod {
+    type Response = TelePeriodResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "TelePeriod".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+            .into()
+    }
+}
+
+/// Restart the device
+///
+/// `1` restarts the device normally, `99` forces a restart without saving the settings.
+#[derive(Debug, Clone)]
+pub struct Restart(pub u8);
+
+/// Reply to [`Restart`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct RestartResponse {
+    #[serde(rename = "Restart")]
+    pub restart: String,
+}
+
+impl TasmotaCommand for Restart {
+    type Response = RestartResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "Restart".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0.to_string().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn on_off_values() {
+        for value in ["ON", "on", "1"] {
+            assert!(on_off(json!(value)).unwrap(), "{value}");
+        }
+        for value in ["OFF", "off", "0"] {
+            assert!(!on_off(json!(value)).unwrap(), "{value}");
+        }
+        assert!(on_off(json!("TOGGLE")).is_err());
+        assert!(on_off(json!(true)).is_err());
+        assert_eq!(
+            serde_json::from_value::<PowerState>(json!("ON")).unwrap(),
+            PowerState::On
+        );
+    }
+
+    #[test]
+    fn single_value_reply() {
+        assert_eq!(single_value::<_, u8>(json!({"SetOption1": 5})).unwrap(), 5);
+        assert_eq!(
+            single_value::<_, String>(json!({"POWER2": "OFF"})).unwrap(),
+            "OFF"
+        );
+        let error = single_value::<_, u8>(json!({})).unwrap_err();
+        assert!(error.to_string().contains("empty reply"), "{error}");
+        assert!(single_value::<_, u8>(json!(5)).is_err());
+        assert!(single_value::<_, u8>(json!({"SetOption1": "ON"})).is_err());
+    }
+
+    #[test]
+    fn set_option_values() {
+        let parse = |value: Value| serde_json::from_value::<SetOptionValue>(value).ok();
+        assert_eq!(parse(json!(1)), Some(SetOptionValue(1)));
+        assert_eq!(parse(json!(42)), Some(SetOptionValue(42)));
+        assert_eq!(parse(json!("ON")), Some(SetOptionValue(1)));
+        assert_eq!(parse(json!("off")), Some(SetOptionValue(0)));
+        assert_eq!(parse(json!(300)), None);
+        assert_eq!(parse(json!(-1)), None);
+        assert_eq!(parse(json!(true)), None);
+
+        let response: SetOptionResponse =
+            serde_json::from_value(json!({"SetOption19": "OFF"})).unwrap();
+        assert_eq!(response.0, SetOptionValue(0));
+    }
+}
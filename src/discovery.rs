@@ -0,0 +1,473 @@
+use crate::boot::BootInfo;
+use crate::error::MqttError;
+use crate::mqtt::{Delivery, MqttHelper, OverflowPolicy, Subscription};
+use crate::{DeviceUpdate, Result, TopicPrefix, TopicScheme};
+use async_stream::stream;
+use rumqttc::Publish;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::select;
+use tokio::spawn;
+use tokio::sync::broadcast::Sender;
+use tokio::task::JoinHandle;
+use tokio_stream::{Stream, StreamExt, StreamMap};
+use tracing::{debug, warn};
+
+/// Information about a discovered device
+///
+/// Devices running firmware without support for tasmota discovery, or with discovery disabled, only
+/// have their `topic` set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Topic of the device, used to send commands to it
+    pub topic: String,
+    pub ip: Option<IpAddr>,
+    pub hostname: Option<String>,
+    pub device_name: Option<String>,
+    pub friendly_names: Vec<String>,
+    pub firmware_version: Option<String>,
+    pub mac: Option<String>,
+    /// Name of the configured module or template
+    pub model: Option<String>,
+    /// Full topic pattern, like `%prefix%/%topic%/`
+    pub full_topic: Option<String>,
+    /// Command, stat and telemetry prefixes
+    pub prefixes: Option<[String; 3]>,
+    /// Names of the sensors connected to the device
+    pub sensors: Vec<String>,
+}
+
+impl DeviceInfo {
+    fn from_topic(topic: &str) -> Self {
+        DeviceInfo {
+            topic: topic.into(),
+            ..DeviceInfo::default()
+        }
+    }
+
+    /// The topic layout used by the device, if it was published through tasmota discovery
+    pub fn topic_scheme(&self) -> Option<TopicScheme> {
+        let full_topic = self.full_topic.as_deref()?;
+        let prefixes = self.prefixes.clone()?;
+        let mut full_topic = full_topic.to_string();
+        if let Some(hostname) = &self.hostname {
+            full_topic = full_topic.replace("%hostname%", hostname);
+        }
+        if let Some(mac) = &self.mac {
+            let mac = mac.replace(':', "").to_ascii_uppercase();
+            full_topic = full_topic.replace("%id%", &mac[mac.len().saturating_sub(6)..]);
+        }
+        Some(TopicScheme::new(full_topic, prefixes))
+    }
+}
+
+/// Retained device config published to `tasmota/discovery/<MAC>/config`
+#[derive(Deserialize, Debug)]
+struct DiscoveryConfig {
+    ip: Option<IpAddr>,
+    #[serde(rename = "dn")]
+    device_name: Option<String>,
+    #[serde(rename = "fn", default)]
+    friendly_names: Vec<Option<String>>,
+    #[serde(rename = "hn")]
+    hostname: Option<String>,
+    mac: Option<String>,
+    #[serde(rename = "md")]
+    model: Option<String>,
+    #[serde(rename = "sw")]
+    firmware_version: Option<String>,
+    #[serde(rename = "t")]
+    topic: String,
+    #[serde(rename = "ft")]
+    full_topic: Option<String>,
+    #[serde(rename = "tp")]
+    prefixes: Option<[String; 3]>,
+}
+
+impl From<DiscoveryConfig> for DeviceInfo {
+    fn from(config: DiscoveryConfig) -> Self {
+        DeviceInfo {
+            topic: config.topic,
+            ip: config.ip,
+            hostname: config.hostname,
+            device_name: config.device_name,
+            friendly_names: config.friendly_names.into_iter().flatten().collect(),
+            firmware_version: config.firmware_version,
+            mac: config.mac,
+            model: config.model,
+            full_topic: config.full_topic,
+            prefixes: config.prefixes,
+            sensors: Vec::new(),
+        }
+    }
+}
+
+/// Retained sensor list published to `tasmota/discovery/<MAC>/sensors`
+#[derive(Deserialize, Debug)]
+struct DiscoverySensors {
+    #[serde(rename = "sn")]
+    sensors: Map<String, Value>,
+}
+
+impl DiscoverySensors {
+    fn names(&self) -> Vec<String> {
+        self.sensors
+            .keys()
+            .filter(|name| *name != "Time" && *name != "TempUnit")
+            .cloned()
+            .collect()
+    }
+}
+
+type Deliveries = Pin<Box<dyn Stream<Item = Result<Delivery, MqttError>> + Send>>;
+
+fn deliveries(mut subscription: Subscription) -> Deliveries {
+    Box::pin(stream! {
+        loop {
+            yield subscription.next_delivery().await;
+        }
+    })
+}
+
+#[derive(Default)]
+struct DiscoveryState {
+    /// Devices discovered through tasmota discovery, by mac address
+    configs: HashMap<String, DeviceInfo>,
+    /// Topic layouts we're listening for LWT and boot messages with, by topic filter
+    schemes: HashMap<String, TopicScheme>,
+    /// Boot info of devices that haven't sent all `INFO<n>` messages yet, by topic
+    booting: HashMap<String, BootInfo>,
+}
+
+impl DiscoveryState {
+    /// Process a discovery message, returning the topic layout of the device if it sent its config
+    fn handle_discovery(
+        &mut self,
+        msg: &Publish,
+        known_devices: &Mutex<BTreeMap<String, DeviceInfo>>,
+        tx: &Sender<DeviceUpdate>,
+    ) -> Option<TopicScheme> {
+        let mut levels = msg.topic.split('/').skip(2);
+        let (Some(mac), Some(kind)) = (levels.next(), levels.next()) else {
+            return None;
+        };
+
+        if msg.payload.is_empty() {
+            // retained discovery message has been cleared
+            if kind == "config" {
+                self.configs.remove(mac);
+            }
+            return None;
+        }
+
+        let info = match kind {
+            "config" => {
+                let Ok(config) = serde_json::from_slice::<DiscoveryConfig>(msg.payload.as_ref())
+                else {
+                    debug!(mac, "failed to parse discovery config");
+                    return None;
+                };
+                let mut info = DeviceInfo::from(config);
+                if let Some(existing) = self.configs.get(mac) {
+                    info.sensors = existing.sensors.clone();
+                }
+                self.configs.insert(mac.into(), info);
+                &self.configs[mac]
+            }
+            "sensors" => {
+                let Ok(sensors) = serde_json::from_slice::<DiscoverySensors>(msg.payload.as_ref())
+                else {
+                    debug!(mac, "failed to parse discovery sensors");
+                    return None;
+                };
+                let info = self.configs.get_mut(mac)?;
+                info.sensors = sensors.names();
+                info
+            }
+            _ => return None,
+        };
+
+        debug!(device = info.topic, "processing discovery config");
+        // keep the info of online devices up to date
+        if let Some(known) = known_devices.lock().unwrap().get_mut(&info.topic) {
+            if known != info {
+                *known = info.clone();
+                let _ = tx.send(DeviceUpdate::Added(Box::new(info.clone())));
+            }
+        }
+        info.topic_scheme()
+    }
+
+    fn handle_lwt(
+        &self,
+        filter: &str,
+        msg: &Publish,
+        known_devices: &Mutex<BTreeMap<String, DeviceInfo>>,
+        tx: &Sender<DeviceUpdate>,
+    ) {
+        let payload = std::str::from_utf8(msg.payload.as_ref()).unwrap_or_default();
+        let Some((TopicPrefix::Tele, device, "LWT")) = self
+            .schemes
+            .get(filter)
+            .and_then(|scheme| scheme.parse(&msg.topic))
+        else {
+            return;
+        };
+
+        debug!(
+            message = payload,
+            device = device,
+            "processing discovery message"
+        );
+        let mut known_devices = known_devices.lock().unwrap();
+        match payload {
+            "Online" if !known_devices.contains_key(device) => {
+                let info = self
+                    .configs
+                    .values()
+                    .find(|info| info.topic == device)
+                    .cloned()
+                    .unwrap_or_else(|| DeviceInfo::from_topic(device));
+                known_devices.insert(device.into(), info.clone());
+                let _ = tx.send(DeviceUpdate::Added(Box::new(info)));
+            }
+            "Offline" if known_devices.remove(device).is_some() => {
+                let _ = tx.send(DeviceUpdate::Removed(device.into()));
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_info(
+        &mut self,
+        filter: &str,
+        msg: &Publish,
+        boot_infos: &Mutex<BTreeMap<String, BootInfo>>,
+        tx: &Sender<DeviceUpdate>,
+    ) {
+        let Some((TopicPrefix::Tele, device, name)) = self
+            .schemes
+            .get(filter)
+            .and_then(|scheme| scheme.parse(&msg.topic))
+        else {
+            return;
+        };
+        if !matches!(name, "INFO1" | "INFO2" | "INFO3") {
+            return;
+        }
+
+        debug!(device, name, "processing boot info");
+        if name == "INFO1" {
+            // the first message of a new boot
+            self.booting.insert(device.into(), BootInfo::new(device));
+        }
+        let info = self
+            .booting
+            .entry(device.into())
+            .or_insert_with(|| BootInfo::new(device));
+        if info.update(name, msg.payload.as_ref()) {
+            let info = self.booting.remove(device).unwrap_or_default();
+            boot_infos
+                .lock()
+                .unwrap()
+                .insert(device.into(), info.clone());
+            let _ = tx.send(DeviceUpdate::Booted(Box::new(info)));
+        }
+    }
+}
+
+/// Topic filters for the messages a device publishes when it comes online or goes offline
+fn device_filters(scheme: &TopicScheme) -> [String; 4] {
+    ["LWT", "INFO1", "INFO2", "INFO3"].map(|name| scheme.filter(TopicPrefix::Tele, name))
+}
+
+/// Start tracking which devices are online, returning the handle of the discovery task
+///
+/// LWT and boot messages are received for the default topic layout, and for any other layout used by devices
+/// announced through tasmota discovery.
+pub async fn start_discovery(
+    mqtt: &MqttHelper,
+    default_scheme: TopicScheme,
+    known_devices: Arc<Mutex<BTreeMap<String, DeviceInfo>>>,
+    boot_infos: Arc<Mutex<BTreeMap<String, BootInfo>>>,
+    tx: Sender<DeviceUpdate>,
+) -> Result<JoinHandle<()>> {
+    // all retained messages are received at once, so leave plenty of room
+    let mut discovery = mqtt
+        .subscribe(
+            "tasmota/discovery/#".into(),
+            1024,
+            OverflowPolicy::DropOldest,
+        )
+        .await?;
+    // subscribe after the discovery configs, so the retained configs are received before the device comes online
+    let mut state = DiscoveryState::default();
+    let mut device_messages = StreamMap::new();
+    for filter in device_filters(&default_scheme) {
+        device_messages.insert(
+            filter.clone(),
+            deliveries(
+                mqtt.subscribe(filter.clone(), 1024, OverflowPolicy::DropOldest)
+                    .await?,
+            ),
+        );
+        state.schemes.insert(filter, default_scheme.clone());
+    }
+
+    let mqtt = mqtt.clone();
+    let task = spawn(async move {
+        loop {
+            select! {
+                biased;
+                delivery = discovery.next_delivery() => match delivery {
+                    Ok(Delivery::Message(msg)) => {
+                        let Some(scheme) = state.handle_discovery(&msg, &known_devices, &tx) else {
+                            continue;
+                        };
+                        for filter in device_filters(&scheme) {
+                            if device_messages.contains_key(&filter) {
+                                continue;
+                            }
+                            debug!(filter, "listening for devices with custom topic layout");
+                            match mqtt.subscribe(filter.clone(), 1024, OverflowPolicy::DropOldest).await {
+                                Ok(subscription) => {
+                                    device_messages.insert(filter.clone(), deliveries(subscription));
+                                    state.schemes.insert(filter, scheme.clone());
+                                }
+                                Err(e) => warn!(filter, error = ?e, "failed to subscribe to device messages"),
+                            }
+                        }
+                    }
+                    Ok(Delivery::Lagged(count)) => warn!(count, "missed discovery configs"),
+                    Err(_) => break,
+                },
+                Some((filter, delivery)) = device_messages.next() => match delivery {
+                    Ok(Delivery::Message(msg)) => {
+                        state.handle_lwt(&filter, &msg, &known_devices, &tx);
+                        state.handle_info(&filter, &msg, &boot_infos, &tx);
+                    }
+                    Ok(Delivery::Lagged(count)) => warn!(count, "missed discovery messages"),
+                    Err(_) => break,
+                },
+            }
+        }
+    });
+
+    Ok(task)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumqttc::QoS;
+    use tokio::sync::broadcast::channel;
+
+    const CONFIG: &str = r#"{"ip":"10.0.0.5","dn":"Lamp","fn":["Lamp",null],"hn":"lamp-1","mac":"AABBCCDDEEFF","md":"Sonoff","sw":"13.1.0","t":"lamp","ft":"%prefix%/%topic%/","tp":["cmnd","stat","tele"]}"#;
+
+    fn message(topic: &str, payload: &str) -> Publish {
+        Publish::new(topic, QoS::AtLeastOnce, payload)
+    }
+
+    fn state() -> DiscoveryState {
+        let mut state = DiscoveryState::default();
+        let scheme = TopicScheme::default();
+        for filter in device_filters(&scheme) {
+            state.schemes.insert(filter, scheme.clone());
+        }
+        state
+    }
+
+    #[test]
+    fn config_before_online() {
+        let mut state = state();
+        let known = Mutex::default();
+        let (tx, mut rx) = channel(10);
+
+        let config = message("tasmota/discovery/AABBCCDDEEFF/config", CONFIG);
+        assert!(state.handle_discovery(&config, &known, &tx).is_some());
+        assert!(rx.try_recv().is_err());
+
+        state.handle_lwt(
+            "tele/+/LWT",
+            &message("tele/lamp/LWT", "Online"),
+            &known,
+            &tx,
+        );
+        let Ok(DeviceUpdate::Added(info)) = rx.try_recv() else {
+            panic!("expected the device to be added");
+        };
+        assert_eq!(info.topic, "lamp");
+        assert_eq!(info.hostname.as_deref(), Some("lamp-1"));
+        assert_eq!(info.friendly_names, ["Lamp"]);
+
+        state.handle_lwt(
+            "tele/+/LWT",
+            &message("tele/lamp/LWT", "Offline"),
+            &known,
+            &tx,
+        );
+        assert!(matches!(rx.try_recv(), Ok(DeviceUpdate::Removed(device)) if device == "lamp"));
+        assert!(known.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn config_after_online() {
+        let mut state = state();
+        let known = Mutex::default();
+        let (tx, mut rx) = channel(10);
+
+        state.handle_lwt(
+            "tele/+/LWT",
+            &message("tele/lamp/LWT", "Online"),
+            &known,
+            &tx,
+        );
+        let Ok(DeviceUpdate::Added(info)) = rx.try_recv() else {
+            panic!("expected the device to be added");
+        };
+        assert_eq!(*info, DeviceInfo::from_topic("lamp"));
+
+        let config = message("tasmota/discovery/AABBCCDDEEFF/config", CONFIG);
+        state.handle_discovery(&config, &known, &tx);
+        let Ok(DeviceUpdate::Added(info)) = rx.try_recv() else {
+            panic!("expected the device to be updated");
+        };
+        assert_eq!(info.ip, Some([10, 0, 0, 5].into()));
+        assert_eq!(known.lock().unwrap()["lamp"], *info);
+
+        // an unchanged config doesn't trigger an update
+        state.handle_discovery(&config, &known, &tx);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn sensors_update_online_device() {
+        let mut state = state();
+        let known = Mutex::default();
+        let (tx, mut rx) = channel(10);
+
+        let config = message("tasmota/discovery/AABBCCDDEEFF/config", CONFIG);
+        state.handle_discovery(&config, &known, &tx);
+        state.handle_lwt(
+            "tele/+/LWT",
+            &message("tele/lamp/LWT", "Online"),
+            &known,
+            &tx,
+        );
+        assert!(matches!(rx.try_recv(), Ok(DeviceUpdate::Added(_))));
+
+        let sensors = message(
+            "tasmota/discovery/AABBCCDDEEFF/sensors",
+            r#"{"sn":{"Time":"2024-01-01T00:00:00","AM2301":{"Temperature":21.5},"TempUnit":"C"}}"#,
+        );
+        state.handle_discovery(&sensors, &known, &tx);
+        let Ok(DeviceUpdate::Added(info)) = rx.try_recv() else {
+            panic!("expected the device to be updated");
+        };
+        assert_eq!(info.sensors, ["AM2301"]);
+    }
+}
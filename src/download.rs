@@ -1,6 +1,6 @@
-use crate::error::DownloadError;
-use crate::mqtt::MqttHelper;
-use crate::{DeviceUpdate, Result};
+use crate::error::{DownloadError, SettingsError};
+use crate::mqtt::{MqttHelper, OverflowPolicy};
+use crate::{DeviceUpdate, Result, TasmotaSettings, TopicPrefix, TopicScheme};
 use bytes::{Bytes, BytesMut};
 use md5::{Digest, Md5};
 use serde::{Deserialize, Serialize};
@@ -33,6 +33,25 @@ pub struct DownloadedFile {
     pub md5: [u8; 16],
 }
 
+impl DownloadedFile {
+    /// Create a file from existing data, for example a config backup loaded from disk
+    pub fn new(name: impl Into<String>, data: impl Into<Bytes>) -> Self {
+        let data = data.into();
+        let mut hasher = Md5::new();
+        hasher.update(data.as_ref());
+        DownloadedFile {
+            name: name.into(),
+            data,
+            md5: hasher.finalize().into(),
+        }
+    }
+
+    /// Decode the settings contained in a config backup
+    pub fn settings(&self) -> Result<TasmotaSettings, SettingsError> {
+        TasmotaSettings::decode(&self.data)
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "PascalCase")]
 struct DownloadResponse<'a> {
@@ -47,14 +66,19 @@ struct DownloadResponse<'a> {
 
 pub async fn download_config(
     mqtt: &MqttHelper,
+    topics: &TopicScheme,
     client: &str,
     password: &str,
     mut device_update: Receiver<DeviceUpdate>,
 ) -> Result<DownloadedFile> {
     let mut rx = mqtt
-        .subscribe(format!("stat/{client}/FILEDOWNLOAD"))
+        .subscribe(
+            topics.topic(TopicPrefix::Stat, client, "FILEDOWNLOAD"),
+            10,
+            OverflowPolicy::Disconnect,
+        )
         .await?;
-    let topic = format!("cmnd/{client}/FILEDOWNLOAD");
+    let topic = topics.topic(TopicPrefix::Command, client, "FILEDOWNLOAD");
 
     mqtt.send(
         &topic,
@@ -71,7 +95,7 @@ pub async fn download_config(
     loop {
         let msg = select! {
             msg = rx.recv() => {
-                msg.unwrap()
+                msg?
             }
             discovery = device_update.recv() => {
                 if let Ok(DeviceUpdate::Removed(device)) = discovery {
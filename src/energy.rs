@@ -0,0 +1,343 @@
+//! Energy monitoring for power metering plugs
+
+use crate::commands::{single_value, Status, TasmotaCommand};
+use crate::telemetry::{SensorReading, Telemetry};
+use crate::{Error, Result, TasmotaClient};
+use serde::Deserialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::time::Duration;
+use tokio::time::sleep;
+use tokio_stream::{Stream, StreamExt};
+
+/// Time for the energy monitor to measure with a new calibration
+const CALIBRATION_SETTLE_TIME: Duration = Duration::from_secs(2);
+/// Maximum relative difference between the calibrated and measured value
+const CALIBRATION_TOLERANCE: f64 = 0.02;
+
+/// Reading of an energy monitor
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EnergyReading {
+    pub total_start_time: Option<String>,
+    /// Total energy in kWh
+    pub total: f64,
+    /// Energy used yesterday in kWh
+    pub yesterday: f64,
+    /// Energy used today in kWh
+    pub today: f64,
+    /// Active power in W
+    pub power: f64,
+    /// Apparent power in VA
+    pub apparent_power: Option<f64>,
+    /// Reactive power in VAr
+    pub reactive_power: Option<f64>,
+    pub factor: Option<f64>,
+    /// Voltage in V
+    pub voltage: Option<f64>,
+    /// Current in A
+    pub current: Option<f64>,
+}
+
+/// One of the energy counters kept by the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnergyCounter {
+    Today = 1,
+    Yesterday = 2,
+    Total = 3,
+}
+
+/// Reset or set an energy counter
+///
+/// The value is in Wh, without a value the counter is reset to 0.
+#[derive(Debug, Clone)]
+pub struct EnergyReset {
+    pub counter: EnergyCounter,
+    pub value: Option<u32>,
+}
+
+/// Reply to [`EnergyReset`], the counters after the reset
+#[derive(Debug, Clone, Deserialize)]
+pub struct EnergyResetResponse {
+    #[serde(rename = "EnergyReset")]
+    pub counters: EnergyCounters,
+}
+
+/// Values of the energy counters in kWh
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EnergyCounters {
+    pub total: f64,
+    pub yesterday: f64,
+    pub today: f64,
+}
+
+impl TasmotaCommand for EnergyReset {
+    type Response = EnergyResetResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        format!("EnergyReset{}", self.counter as u8).into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.value.unwrap_or_default().to_string().into()
+    }
+
+    fn reply_key(&self) -> Cow<'_, str> {
+        "EnergyReset".into()
+    }
+}
+
+/// A calibration of the energy monitor, using a known load
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Calibration {
+    /// Calibrate to the given active power in W
+    Power(f64),
+    /// Calibrate to the given voltage in V
+    Voltage(f64),
+    /// Calibrate to the given current in A
+    Current(f64),
+}
+
+impl Calibration {
+    /// The value the calibration should result in
+    pub fn expected(&self) -> f64 {
+        match self {
+            Calibration::Power(value)
+            | Calibration::Voltage(value)
+            | Calibration::Current(value) => *value,
+        }
+    }
+
+    /// The value measured by the device for the calibrated quantity
+    pub fn measured(&self, reading: &EnergyReading) -> Option<f64> {
+        match self {
+            Calibration::Power(_) => Some(reading.power),
+            Calibration::Voltage(_) => reading.voltage,
+            Calibration::Current(_) => reading.current,
+        }
+    }
+}
+
+/// Reply to [`Calibration`], the resulting calibration value
+///
+/// This is a number, or an array for devices with multiple channels.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct CalibrationResponse(#[serde(deserialize_with = "single_value")] pub Value);
+
+impl TasmotaCommand for Calibration {
+    type Response = CalibrationResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        match self {
+            Calibration::Power(_) => "PowerSet",
+            Calibration::Voltage(_) => "VoltageSet",
+            Calibration::Current(_) => "CurrentSet",
+        }
+        .into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        match self {
+            // the current is set in whole mA
+            Calibration::Current(value) => format!("{:.0}", value * 1000.0),
+            Calibration::Power(value) | Calibration::Voltage(value) => value.to_string(),
+        }
+        .into()
+    }
+}
+
+impl TasmotaClient {
+    /// Get the current reading of the energy monitor of a device
+    #[tracing::instrument(skip(self))]
+    pub async fn energy(&self, device: &str) -> Result<EnergyReading> {
+        let sensors = self.execute(device, &Status(8)).await?.0;
+        match sensors.get("ENERGY") {
+            Some(energy) => Ok(EnergyReading::deserialize(energy)?),
+            None => Err(Error::MalformedReply("energy reading", sensors.to_string())),
+        }
+    }
+
+    /// Reset an energy counter of a device to zero, returning the counters after the reset
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// # use tasmota_mqtt_client::energy::EnergyCounter;
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let counters = client.reset_energy("tasmota_device", EnergyCounter::Total).await?;
+    /// println!("total after reset: {}kWh", counters.total);
+    ///     # Ok(())
+    /// # }
+    /// ```
+    #[tracing::instrument(skip(self))]
+    pub async fn reset_energy(
+        &self,
+        device: &str,
+        counter: EnergyCounter,
+    ) -> Result<EnergyCounters> {
+        Ok(self
+            .execute(
+                device,
+                &EnergyReset {
+                    counter,
+                    value: None,
+                },
+            )
+            .await?
+            .counters)
+    }
+
+    /// Receive the energy readings from the telemetry published by devices
+    ///
+    /// The filter is either the topic of a single device, or `+` to receive the readings of all devices.
+    /// Readings are published every `TelePeriod` seconds, see [`telemetry`](Self::telemetry).
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use std::pin::pin;
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// # use tokio_stream::StreamExt;
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let mut readings = pin!(client.energy_readings("+"));
+    /// while let Some(reading) = readings.next().await {
+    ///     let (device, reading) = reading?;
+    ///     println!("{device}: {}W, {}kWh today", reading.power, reading.today);
+    /// }
+    ///     # Ok(())
+    /// # }
+    /// ```
+    pub fn energy_readings<'a>(
+        &'a self,
+        device_filter: &'a str,
+    ) -> impl Stream<Item = Result<(String, EnergyReading)>> + 'a {
+        self.telemetry(device_filter)
+            .filter_map(|telemetry| match telemetry {
+                Ok(Telemetry::Sensor {
+                    device,
+                    mut sensors,
+                }) => match sensors.readings.remove("ENERGY") {
+                    Some(SensorReading::Energy(reading)) => Some(Ok((device, reading))),
+                    _ => None,
+                },
+                Ok(Telemetry::State { .. }) => None,
+                Err(e) => Some(Err(e)),
+            })
+    }
+
+    /// Calibrate the energy monitor of a device using a known load
+    ///
+    /// After calibrating, the reading of the device is checked against the calibrated value,
+    /// failing with [`Error::CalibrationMismatch`] if the measured value is off by more than 2%.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// # use tasmota_mqtt_client::energy::Calibration;
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// // with a 60W incandescent bulb connected
+    /// client.calibrate_energy("tasmota_device", Calibration::Power(60.0)).await?;
+    /// client.calibrate_energy("tasmota_device", Calibration::Voltage(230.0)).await?;
+    ///     # Ok(())
+    /// # }
+    /// ```
+    #[tracing::instrument(skip(self))]
+    pub async fn calibrate_energy(
+        &self,
+        device: &str,
+        calibration: Calibration,
+    ) -> Result<EnergyReading> {
+        self.execute(device, &calibration).await?;
+        sleep(CALIBRATION_SETTLE_TIME).await;
+
+        let reading = self.energy(device).await?;
+        let expected = calibration.expected();
+        let measured = calibration.measured(&reading).ok_or_else(|| {
+            Error::MalformedReply("calibrated energy reading", format!("{reading:?}"))
+        })?;
+        if (measured - expected).abs() > expected.abs() * CALIBRATION_TOLERANCE {
+            return Err(Error::CalibrationMismatch(expected, measured));
+        }
+        Ok(reading)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn energy_reset_command() {
+        let reset = EnergyReset {
+            counter: EnergyCounter::Total,
+            value: None,
+        };
+        assert_eq!(reset.name(), "EnergyReset3");
+        assert_eq!(reset.payload(), "0");
+
+        let set = EnergyReset {
+            counter: EnergyCounter::Today,
+            value: Some(1500),
+        };
+        assert_eq!(set.name(), "EnergyReset1");
+        assert_eq!(set.payload(), "1500");
+    }
+
+    #[test]
+    fn parse_energy_reset_response() {
+        let response: EnergyResetResponse = serde_json::from_str(
+            r#"{"EnergyReset":{"Total":0.000,"Yesterday":0.123,"Today":0.456}}"#,
+        )
+        .unwrap();
+        assert_eq!(response.counters.total, 0.0);
+        assert_eq!(response.counters.yesterday, 0.123);
+        assert_eq!(response.counters.today, 0.456);
+    }
+
+    #[test]
+    fn calibration() {
+        let reading: EnergyReading = serde_json::from_str(
+            r#"{"TotalStartTime":"2024-01-01T00:00:00","Total":1.5,"Yesterday":0.2,"Today":0.1,"Power":59,"ApparentPower":60,"ReactivePower":10,"Factor":0.98,"Voltage":231,"Current":0.26}"#,
+        )
+        .unwrap();
+
+        let power = Calibration::Power(60.0);
+        assert_eq!(power.name(), "PowerSet");
+        assert_eq!(power.payload(), "60");
+        assert_eq!(power.measured(&reading), Some(59.0));
+
+        let current = Calibration::Current(0.25);
+        assert_eq!(current.name(), "CurrentSet");
+        assert_eq!(current.payload(), "250");
+        assert_eq!(current.expected(), 0.25);
+        assert_eq!(current.measured(&reading), Some(0.26));
+
+        // 0.29 * 1000.0 isn't exactly 290
+        assert_eq!(Calibration::Current(0.29).payload(), "290");
+    }
+}
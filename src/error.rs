@@ -1,3 +1,4 @@
+use crate::settings::SettingsVersion;
 use hex::FromHexError;
 use rumqttc::{ClientError, ConnectionError};
 use thiserror::Error;
@@ -13,10 +14,24 @@ pub enum Error {
     JsonPayload(serde_json::Error),
     #[error(transparent)]
     Download(#[from] DownloadError),
+    #[error(transparent)]
+    Upload(#[from] UploadError),
+    #[error(transparent)]
+    Settings(#[from] SettingsError),
     #[error("Malformed reply received from device for {0}: {1}")]
     MalformedReply(&'static str, String),
     #[error("Timeout while waiting for reply from device")]
     Timeout,
+    #[error("Device rejected command {0}: {1}")]
+    CommandFailed(String, String),
+    #[error("Calibration didn't result in the expected value, expected {0} but measured {1}")]
+    CalibrationMismatch(f64, f64),
+    #[error("Rule set {0} stored on the device doesn't match the rule text that was sent")]
+    RuleMismatch(u8),
+    #[error("Invalid template: {0}")]
+    InvalidTemplate(String),
+    #[error("Device didn't apply the {0} after restarting")]
+    ConfigurationMismatch(&'static str),
 }
 
 impl From<serde_json::Error> for Error {
@@ -29,11 +44,19 @@ impl From<serde_json::Error> for Error {
 #[non_exhaustive]
 pub enum MqttError {
     #[error("transparent")]
-    Client(ClientError),
+    Client(Box<ClientError>),
     #[error("transparent")]
-    Connection(ConnectionError),
+    Connection(Box<ConnectionError>),
     #[error("connection closed unexpectedly")]
     Eof,
+    #[error("subscriber didn't keep up with incoming messages")]
+    Overflow,
+    #[error("broker rejected the subscription to {0}")]
+    SubscribeFailed(String),
+    #[error("broker didn't acknowledge the subscription to {0}")]
+    SubscribeTimeout(String),
+    #[error("client has been shut down")]
+    Shutdown,
 }
 
 impl From<MqttError> for Error {
@@ -44,13 +67,13 @@ impl From<MqttError> for Error {
 
 impl From<ClientError> for Error {
     fn from(value: ClientError) -> Self {
-        MqttError::Client(value).into()
+        MqttError::Client(Box::new(value)).into()
     }
 }
 
 impl From<ConnectionError> for Error {
     fn from(value: ConnectionError) -> Self {
-        MqttError::Connection(value).into()
+        MqttError::Connection(Box::new(value)).into()
     }
 }
 
@@ -82,3 +105,53 @@ impl From<FromHexError> for DownloadError {
         DownloadError::InvalidHash
     }
 }
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum UploadError {
+    #[error("Aborted")]
+    UploadAborted,
+    #[error("Invalid password for device")]
+    InvalidPassword,
+    #[error("Bad chunk size")]
+    BadChunkSize,
+    #[error("Invalid file type")]
+    InvalidFileType,
+    #[error("Received error code: {0}")]
+    Unknown(String),
+    #[error("Received an invalid md5 hash")]
+    InvalidHash,
+    #[error(
+        "Device reported a different md5 hash than the uploaded data, expected {0:x?} got {1:x?}"
+    )]
+    MismatchedHash([u8; 16], [u8; 16]),
+    #[error("Device has disconnected during the upload")]
+    Gone,
+    #[error("Device is still running firmware {0} after the update")]
+    FirmwareUnchanged(String),
+}
+
+impl From<FromHexError> for UploadError {
+    fn from(_: FromHexError) -> Self {
+        UploadError::InvalidHash
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SettingsError {
+    #[error("Settings dump has an invalid size of {0} bytes")]
+    InvalidSize(usize),
+    #[error("Settings from firmware version {0} aren't supported")]
+    UnsupportedVersion(SettingsVersion),
+    #[error("Invalid firmware version {0}")]
+    InvalidVersion(String),
+    #[error("Settings checksum doesn't match, expected {0:#x} got {1:#x}")]
+    MismatchedChecksum(u32, u32),
+    #[error("Settings text doesn't fit, {0} bytes needed but only {1} available")]
+    TextPoolFull(usize, usize),
+    #[error("Rule{0} is too long with {1} bytes")]
+    RuleTooLong(usize, usize),
+    #[error("Templates are only supported in settings from ESP8266 devices")]
+    UnsupportedTemplate,
+}
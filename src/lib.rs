@@ -1,51 +1,197 @@
 #![doc = include_str!("../README.md")]
 
+mod boot;
+mod builder;
+pub mod commands;
+mod discovery;
 mod download;
+pub mod energy;
 mod error;
+pub mod light;
 mod mqtt;
+pub mod relay;
+pub mod rules;
+mod settings;
+pub mod shutter;
+mod status;
+mod telemetry;
+pub mod template;
+pub mod timers;
+mod topic;
+mod trie;
+mod upload;
 
+pub use crate::boot::{BootInfo, RestartReason};
+pub use crate::builder::TasmotaClientBuilder;
+use crate::builder::{DEFAULT_RESTART_TIMEOUT, DEFAULT_TIMEOUT, DEFAULT_UPLOAD_TIMEOUT};
+pub use crate::commands::TasmotaCommand;
+use crate::commands::{Restart, Status};
+use crate::discovery::start_discovery;
+pub use crate::discovery::DeviceInfo;
 use crate::download::download_config;
 pub use crate::download::DownloadedFile;
-use crate::error::MqttError;
-use crate::mqtt::MqttHelper;
-pub use error::{Error, Result};
+pub use crate::energy::EnergyReading;
+pub use crate::mqtt::ConnectionState;
+use crate::mqtt::{MqttHelper, OverflowPolicy, Subscription};
+pub use crate::settings::{
+    MqttSettings, SetOptions, SettingsVersion, TasmotaSettings, TemplateSettings, TimerSettings,
+};
+pub use crate::status::{
+    DeviceStatus, StatusDevice, StatusFirmware, StatusMemory, StatusMqtt, StatusNetwork,
+    StatusParameters, StatusState, StatusTime, StatusWifi,
+};
+pub use crate::telemetry::{
+    ClimateReading, SensorReading, SensorTelemetry, StateTelemetry, Telemetry, TemperatureReading,
+};
+pub use crate::topic::{TopicPrefix, TopicScheme};
+pub use crate::upload::UploadProgress;
+use crate::upload::{upload_config, upload_file, UploadType};
+use async_stream::{stream, try_stream};
+use bytes::Bytes;
+use dashmap::DashMap;
+pub use error::{DownloadError, Error, MqttError, Result, SettingsError, UploadError};
 use rumqttc::MqttOptions;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
-use std::collections::BTreeSet;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::net::IpAddr;
+use std::pin::pin;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::spawn;
-use tokio::sync::broadcast::{channel, Sender};
-use tokio::time::timeout;
-use tokio_stream::wrappers::BroadcastStream;
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{channel, Receiver as BroadcastReceiver, Sender};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use tokio::task::JoinHandle;
+use tokio::time::{timeout_at, Instant};
+use tokio_stream::wrappers::{BroadcastStream, WatchStream};
 use tokio_stream::{Stream, StreamExt};
-use tracing::debug;
+use tracing::{debug, warn};
+
+/// Maximum time to wait for a device to come back online after a firmware update
+const FIRMWARE_RESTART_TIMEOUT: Duration = Duration::from_secs(120);
+/// First bytes of a gzip compressed file
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Time to wait for the reply of a command after a failure that might belong to a different command
+const COMMAND_FAILURE_GRACE: Duration = Duration::from_millis(200);
 
 /// A client for interacting with tasmota devices over MQTT
 pub struct TasmotaClient {
     mqtt: MqttHelper,
-    known_devices: Arc<Mutex<BTreeSet<String>>>,
+    known_devices: Arc<Mutex<BTreeMap<String, DeviceInfo>>>,
+    boot_infos: Arc<Mutex<BTreeMap<String, BootInfo>>>,
     device_update: Sender<DeviceUpdate>,
     timeout: Duration,
+    restart_timeout: Duration,
+    upload_timeout: Duration,
+    topic_scheme: TopicScheme,
+    command_locks: DashMap<String, Arc<AsyncMutex<()>>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// Held while a command for a device is in flight
+///
+/// The lock of the device is removed once no other command is using or waiting for it.
+struct CommandGuard<'a> {
+    locks: &'a DashMap<String, Arc<AsyncMutex<()>>>,
+    device: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl<'a> CommandGuard<'a> {
+    async fn acquire(locks: &'a DashMap<String, Arc<AsyncMutex<()>>>, device: &str) -> Self {
+        let lock = locks.entry(device.into()).or_default().clone();
+        // construct the guard before waiting, so the lock is also cleaned up if the wait is cancelled
+        let mut guard = CommandGuard {
+            locks,
+            device: device.into(),
+            guard: None,
+        };
+        guard.guard = Some(lock.lock_owned().await);
+        guard
+    }
+}
+
+impl Drop for CommandGuard<'_> {
+    fn drop(&mut self) {
+        // release the lock first, so only the map holds a reference if nobody else is waiting
+        self.guard.take();
+        self.locks
+            .remove_if(&self.device, |_, lock| Arc::strong_count(lock) == 1);
+    }
+}
+
+impl Drop for TasmotaClient {
+    fn drop(&mut self) {
+        self.mqtt.shutdown();
+    }
+}
+
+/// Progress of a firmware upload.
+///
+/// See also [`TasmotaClient::upload_firmware`].
+#[derive(Debug, Clone)]
+pub enum FirmwareUpdate {
+    /// Part of the firmware has been uploaded to the device
+    Progress(UploadProgress),
+    /// The firmware has been uploaded and verified, the device is restarting
+    Restarting,
+    /// The device is back online after the update
+    Online {
+        /// Firmware version from before the update
+        previous: String,
+        /// Firmware version the device is now running
+        version: String,
+    },
 }
 
 /// A device has been added or removed.
 ///
 /// See also [`TasmotaClient::devices`].
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum DeviceUpdate {
     /// A new device has been discovered, or a previously offline device has come back
-    Added(String),
-    /// A previously discovered device has gone offline
+    ///
+    /// Also sent for devices that are already online when their discovery config changes.
+    Added(Box<DeviceInfo>),
+    /// A previously discovered device has gone offline, identified by its topic
     Removed(String),
+    /// A device has booted and published its boot info
+    Booted(Box<BootInfo>),
+}
+
+/// How a device should restart
+///
+/// See also [`TasmotaClient::restart`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartMode {
+    /// Save the settings and restart
+    Normal = 1,
+    /// Restart into the safeboot partition, only supported on ESP32
+    SafeBoot = 3,
+    /// Restart without saving the settings
+    WithoutSaving = 99,
+}
+
+/// Result of a restart, to confirm that the device actually rebooted
+///
+/// See also [`TasmotaClient::restart`].
+#[derive(Debug, Clone)]
+pub struct RestartInfo {
+    /// Boot count from before the restart
+    pub previous_boot_count: u32,
+    pub boot_count: u32,
+    /// Uptime after the restart, like `0T00:00:12`
+    pub uptime: String,
+    pub restart_reason: String,
 }
 
 impl TasmotaClient {
-    /// Connect to an MQTT server to allow access to tasmota devices connected to the same server
+    /// Configure a connection to an MQTT server to allow access to tasmota devices connected to the same server
     ///
     /// # Usage
     ///
@@ -53,72 +199,80 @@ impl TasmotaClient {
     /// # use tasmota_mqtt_client::{Result, TasmotaClient};
     /// # #[tokio::main]
     /// # async fn main() -> Result<()> {
-    /// let client = TasmotaClient::connect(
-    ///     "mqtt.example.com",
-    ///     1883,
-    ///     Some(("mqtt_username", "mqtt_password")),
-    /// ).await?;
+    /// let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     .credentials("mqtt_username", "mqtt_password")
+    ///     .connect()
+    ///     .await?;
     /// #   Ok(())
     /// # }
     /// ```
     ///
-    pub async fn connect(host: &str, port: u16, credentials: Option<(&str, &str)>) -> Result<Self> {
-        let mut mqtt_opts = MqttOptions::new("tasmota-client", host, port);
-        if let Some((username, password)) = credentials {
-            mqtt_opts.set_credentials(username, password);
-        }
-        Self::from_mqtt_options(mqtt_opts).await
+    pub fn builder(host: impl Into<String>, port: u16) -> TasmotaClientBuilder {
+        TasmotaClientBuilder::new(host, port)
     }
 
     /// Connect to an MQTT server using an existing [`MqttOptions`].
     pub async fn from_mqtt_options(options: MqttOptions) -> Result<Self> {
-        let mqtt = MqttHelper::connect(options);
-
-        let mut lwt = mqtt.subscribe("tele/+/LWT".into()).await?;
-
-        let known_devices = Arc::new(Mutex::new(BTreeSet::new()));
-
-        let edit_devices = known_devices.clone();
-
-        let (tx, _) = channel(10);
-        let device_update = tx.clone();
+        Self::connect_with(options, TopicScheme::default(), DEFAULT_TIMEOUT).await
+    }
 
-        spawn(async move {
-            while let Some(msg) = lwt.recv().await {
-                let payload = std::str::from_utf8(msg.payload.as_ref()).unwrap_or_default();
-                let Some(device) = msg.topic.split('/').nth(1) else {
-                    continue;
-                };
+    async fn connect_with(
+        options: MqttOptions,
+        topic_scheme: TopicScheme,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let (mqtt, event_loop) = MqttHelper::connect(options);
 
-                debug!(
-                    message = payload,
-                    device = device,
-                    "processing discovery message"
-                );
-                match payload {
-                    "Online" => {
-                        if edit_devices.lock().unwrap().insert(device.into()) {
-                            let _ = tx.send(DeviceUpdate::Added(device.into()));
-                        }
-                    }
-                    "Offline" => {
-                        if edit_devices.lock().unwrap().remove(device) {
-                            let _ = tx.send(DeviceUpdate::Removed(device.into()));
-                        }
-                    }
-                    _ => {}
-                }
+        let known_devices = Arc::new(Mutex::new(BTreeMap::new()));
+        let boot_infos = Arc::new(Mutex::new(BTreeMap::new()));
+        // restarts wait for their device on this channel, so leave room for bursts of discovery updates
+        let (device_update, _) = channel(64);
+        let discovery = match start_discovery(
+            &mqtt,
+            topic_scheme.clone(),
+            known_devices.clone(),
+            boot_infos.clone(),
+            device_update.clone(),
+        )
+        .await
+        {
+            Ok(discovery) => discovery,
+            Err(e) => {
+                mqtt.shutdown();
+                return Err(e);
             }
-        });
+        };
 
         Ok(TasmotaClient {
             mqtt,
             known_devices,
+            boot_infos,
             device_update,
-            timeout: Duration::from_secs(1),
+            timeout,
+            restart_timeout: DEFAULT_RESTART_TIMEOUT,
+            upload_timeout: DEFAULT_UPLOAD_TIMEOUT,
+            topic_scheme,
+            command_locks: DashMap::new(),
+            tasks: Mutex::new(vec![event_loop, discovery]),
         })
     }
 
+    /// Disconnect from the MQTT server and stop all background tasks
+    ///
+    /// Commands and file transfers that are still in progress fail with [`MqttError::Shutdown`],
+    /// and all [`devices`](Self::devices) streams end.
+    ///
+    /// Dropping the client also stops the background tasks, but doesn't wait for them to finish.
+    pub async fn shutdown(&self) {
+        self.mqtt.shutdown();
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            if let Err(e) = task.await {
+                warn!(error = ?e, "background task failed");
+            }
+        }
+    }
+
     /// Set the timeout used for one-show commands
     ///
     /// The default timeout is 1 second
@@ -126,6 +280,65 @@ impl TasmotaClient {
         self.timeout = timeout;
     }
 
+    /// Set the time a device gets to come back online after a restart
+    ///
+    /// The default is 60 seconds
+    pub fn set_restart_timeout(&mut self, restart_timeout: Duration) {
+        self.restart_timeout = restart_timeout;
+    }
+
+    /// Set the time a device gets to acknowledge every chunk of a config or firmware upload
+    ///
+    /// The default is 10 seconds
+    pub fn set_upload_timeout(&mut self, upload_timeout: Duration) {
+        self.upload_timeout = upload_timeout;
+    }
+
+    /// Watch the state of the connection to the MQTT server
+    ///
+    /// The stream starts with the current state. After the connection is lost, the client keeps trying to reconnect
+    /// with an increasing delay, and restores all subscriptions once it is connected again. Devices that went offline
+    /// in the meantime are reported as removed once their retained LWT message is received again.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use std::pin::pin;
+    /// # use tasmota_mqtt_client::{ConnectionState, Result, TasmotaClient};
+    /// # use tokio_stream::StreamExt;
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let mut states = pin!(client.connection_state());
+    /// while let Some(state) = states.next().await {
+    ///     if let ConnectionState::Disconnected(reason) = state {
+    ///         println!("lost connection: {reason}");
+    ///     }
+    /// }
+    ///     # Ok(())
+    /// # }
+    /// ```
+    pub fn connection_state(&self) -> impl Stream<Item = ConnectionState> {
+        WatchStream::new(self.mqtt.connection_state())
+    }
+
+    /// Get the topic layout used by a device
+    ///
+    /// This is the layout published by the device through tasmota discovery if available, or the layout of the client otherwise.
+    pub fn topic_scheme(&self, device: &str) -> TopicScheme {
+        self.known_devices
+            .lock()
+            .unwrap()
+            .get(device)
+            .and_then(DeviceInfo::topic_scheme)
+            .unwrap_or_else(|| self.topic_scheme.clone())
+    }
+
     /// Download the config backup from a device
     ///
     /// The password is the mqtt password used by the device, which might be different from the mqtt password used by this client
@@ -140,11 +353,10 @@ impl TasmotaClient {
     /// #
     /// # #[tokio::main]
     /// # async fn main() -> Result<()> {
-    ///     # let client = TasmotaClient::connect(
-    ///     #     "mqtt.example.com",
-    ///     #     1883,
-    ///     #     Some(("mqtt_username", "mqtt_password")),
-    ///     # ).await?;
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
     /// // let client: TasmotaClient = ...
     /// let download = client.download_config("tasmota_device", "tasmota_device_mqtt_password").await?;
     /// println!("downloaded config file {} of {} bytes", download.name, download.data.len());
@@ -153,7 +365,150 @@ impl TasmotaClient {
     /// ```
     #[tracing::instrument(skip(self))]
     pub async fn download_config(&self, client: &str, password: &str) -> Result<DownloadedFile> {
-        download_config(&self.mqtt, client, password, self.device_update.subscribe()).await
+        download_config(
+            &self.mqtt,
+            &self.topic_scheme(client),
+            client,
+            password,
+            self.device_update.subscribe(),
+        )
+        .await
+    }
+
+    /// Restore a config backup to a device
+    ///
+    /// The password is the mqtt password used by the device, which might be different from the mqtt password used by this client.
+    ///
+    /// The device will restart after the config has been applied.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let backup = client.download_config("tasmota_device", "tasmota_device_mqtt_password").await?;
+    /// client.upload_config("tasmota_device", "tasmota_device_mqtt_password", &backup).await?;
+    ///     # Ok(())
+    /// # }
+    /// ```
+    #[tracing::instrument(skip(self, file), fields(file = file.name))]
+    pub async fn upload_config(
+        &self,
+        client: &str,
+        password: &str,
+        file: &DownloadedFile,
+    ) -> Result<()> {
+        upload_config(
+            &self.mqtt,
+            &self.topic_scheme(client),
+            client,
+            password,
+            file,
+            self.upload_timeout,
+            self.device_update.subscribe(),
+        )
+        .await
+    }
+
+    /// Upload a new firmware to a device
+    ///
+    /// The password is the mqtt password used by the device, which might be different from the mqtt password used by this client.
+    ///
+    /// Both plain and gzip compressed (`.bin.gz`) firmware images are accepted.
+    ///
+    /// The returned stream reports the progress of the upload, after which the device restarts into the new firmware.
+    /// The stream ends once the device has come back online, with the firmware version it is running.
+    /// Fails with [`UploadError::FirmwareUnchanged`] if the device is still running the previous version.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use std::pin::pin;
+    /// # use tasmota_mqtt_client::{FirmwareUpdate, Result, TasmotaClient};
+    /// # use tokio_stream::StreamExt;
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let firmware = std::fs::read("tasmota.bin.gz").unwrap();
+    /// let mut update = pin!(client.upload_firmware("tasmota_device", "tasmota_device_mqtt_password", firmware));
+    /// while let Some(update) = update.next().await {
+    ///     match update? {
+    ///         FirmwareUpdate::Progress(progress) => {
+    ///             println!("uploaded {}/{} bytes", progress.sent, progress.total);
+    ///         }
+    ///         FirmwareUpdate::Restarting => {
+    ///             println!("waiting for device to restart");
+    ///         }
+    ///         FirmwareUpdate::Online { previous, version } => {
+    ///             println!("updated from {previous} to {version}");
+    ///         }
+    ///     }
+    /// }
+    ///     # Ok(())
+    /// # }
+    /// ```
+    pub fn upload_firmware<'a>(
+        &'a self,
+        device: &'a str,
+        password: &'a str,
+        firmware: impl Into<Bytes>,
+    ) -> impl Stream<Item = Result<FirmwareUpdate>> + 'a {
+        let firmware = firmware.into();
+        // tasmota decides whether to decompress the firmware by the extension of the file name
+        let name = if firmware.starts_with(&GZIP_MAGIC) {
+            "tasmota.bin.gz"
+        } else {
+            "tasmota.bin"
+        };
+        let file = DownloadedFile::new(name, firmware);
+        try_stream! {
+            let previous = self.firmware_version(device).await?;
+            let mut device_update = self.device_update.subscribe();
+            let topics = self.topic_scheme(device);
+            let mut boot = self.boot_subscription(&topics, device).await?;
+
+            let mut upload = pin!(upload_file(
+                &self.mqtt,
+                &topics,
+                device,
+                password,
+                &file,
+                UploadType::Firmware,
+                self.upload_timeout,
+                self.device_update.subscribe(),
+            ));
+            while let Some(progress) = upload.next().await {
+                yield FirmwareUpdate::Progress(progress?);
+            }
+
+            yield FirmwareUpdate::Restarting;
+
+            self.wait_restarted(
+                device,
+                &mut device_update,
+                &mut boot,
+                Instant::now() + FIRMWARE_RESTART_TIMEOUT,
+            )
+            .await?;
+
+            let version = self.firmware_version(device).await?;
+            if version == previous {
+                Err(UploadError::FirmwareUnchanged(version.clone()))?;
+            }
+            yield FirmwareUpdate::Online { previous, version };
+        }
     }
 
     /// Get the list of known devices at this point in time
@@ -162,13 +517,27 @@ impl TasmotaClient {
     /// will be unlikely to return all live devices.
     ///
     /// Use [`Self::devices`] if you need to know all live devices.
-    pub fn current_devices(&self) -> Vec<String> {
-        self.known_devices.lock().unwrap().iter().cloned().collect()
+    pub fn current_devices(&self) -> Vec<DeviceInfo> {
+        self.known_devices
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Get the boot info a device published the last time it booted
+    ///
+    /// Only boots that happened while the client was connected are known,
+    /// use [`Self::devices`] to be notified of new boots.
+    pub fn boot_info(&self, device: &str) -> Option<BootInfo> {
+        self.boot_infos.lock().unwrap().get(device).cloned()
     }
 
     /// Subscribe to device discovery, receiving a [`DeviceUpdate`] whenever a device comes online or goes offline
     ///
-    /// This will include an update for any device that is known at the time of calling
+    /// This will include an update for any device that is known at the time of calling.
+    /// The stream ends when the client is shut down.
     ///
     /// # Example
     ///
@@ -180,22 +549,24 @@ impl TasmotaClient {
     /// #
     /// # #[tokio::main]
     /// # async fn main() -> Result<()> {
-    ///     # let client = TasmotaClient::connect(
-    ///     #     "mqtt.example.com",
-    ///     #     1883,
-    ///     #     Some(("mqtt_username", "mqtt_password")),
-    ///     # ).await?;
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
     /// // let client: TasmotaClient = ...
     /// let mut discovery = pin!(client.devices());
     /// while let Some(update) = discovery.next().await {
     ///     match update {
     ///         DeviceUpdate::Added(device) => {
-    ///             let (ip, name) = join!(client.device_ip(&device), client.device_name(&device));
-    ///             println!("discovered {}({device}) with ip {}", name?, ip?);
+    ///             println!("discovered {} with ip {:?}", device.topic, device.ip);
     ///         }
     ///         DeviceUpdate::Removed(device) => {
     ///             println!("{device} has gone offline");
     ///         }
+    ///         DeviceUpdate::Booted(boot) => {
+    ///             println!("{} restarted: {:?}", boot.device, boot.restart_reason);
+    ///         }
+    ///         _ => {}
     ///     }
     /// }
     ///     # Ok(())
@@ -204,13 +575,80 @@ impl TasmotaClient {
     pub fn devices(&self) -> impl Stream<Item = DeviceUpdate> {
         let current = self.current_devices();
         let rx = self.device_update.subscribe();
+        let cancelled = self.mqtt.cancelled();
+
+        stream! {
+            for device in current {
+                yield DeviceUpdate::Added(Box::new(device));
+            }
 
-        tokio_stream::iter(current.into_iter().map(DeviceUpdate::Added))
-            .chain(BroadcastStream::new(rx).filter_map(Result::ok))
+            let mut updates = pin!(BroadcastStream::new(rx).filter_map(Result::ok));
+            let mut cancelled = pin!(cancelled);
+            loop {
+                let update = select! {
+                    update = updates.next() => update,
+                    _ = &mut cancelled => None,
+                };
+                let Some(update) = update else {
+                    break;
+                };
+                yield update;
+            }
+        }
+    }
+
+    /// Restart a device and wait for it to come back online
+    ///
+    /// The restart is detected by the device going offline or by the `INFO1` message a device publishes
+    /// after booting. Fails with [`Error::Timeout`] if the device doesn't come back within the restart timeout,
+    /// see [`set_restart_timeout`](Self::set_restart_timeout).
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use tasmota_mqtt_client::{Result, RestartMode, TasmotaClient};
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let restart = client.restart("tasmota_device", RestartMode::Normal).await?;
+    /// println!("boot count {} -> {}", restart.previous_boot_count, restart.boot_count);
+    ///     # Ok(())
+    /// # }
+    /// ```
+    #[tracing::instrument(skip(self))]
+    pub async fn restart(&self, device: &str, mode: RestartMode) -> Result<RestartInfo> {
+        let previous = self.execute(device, &Status(1)).await?.0;
+        let previous = StatusParameters::deserialize(&previous)?;
+        let deadline = Instant::now() + self.restart_timeout;
+
+        let mut device_update = self.device_update.subscribe();
+        let mut boot = self
+            .boot_subscription(&self.topic_scheme(device), device)
+            .await?;
+        self.execute(device, &Restart(mode as u8)).await?;
+        self.wait_restarted(device, &mut device_update, &mut boot, deadline)
+            .await?;
+
+        let current = self.execute(device, &Status(1)).await?.0;
+        let current = StatusParameters::deserialize(&current)?;
+        Ok(RestartInfo {
+            previous_boot_count: previous.boot_count,
+            boot_count: current.boot_count,
+            uptime: current.uptime,
+            restart_reason: current.restart_reason,
+        })
     }
 
     /// Send a command that expect a single reply message
     ///
+    /// Commands to the same device are sent one at a time, and the reply is matched to the command by its top level key,
+    /// so it is safe to send multiple commands concurrently.
+    ///
     /// # Example
     ///
     /// ```rust,no_run
@@ -222,11 +660,10 @@ impl TasmotaClient {
     /// #
     /// # #[tokio::main]
     /// # async fn main() -> Result<()> {
-    ///     # let client = TasmotaClient::connect(
-    ///     #     "mqtt.example.com",
-    ///     #     1883,
-    ///     #     Some(("mqtt_username", "mqtt_password")),
-    ///     # ).await?;
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
     /// // let client: TasmotaClient = ...
     /// #[derive(Deserialize)]
     /// struct PowerResponse {
@@ -245,24 +682,212 @@ impl TasmotaClient {
         command: &str,
         payload: &str,
     ) -> Result<T> {
-        let mut rx = self.mqtt.subscribe(format!("stat/{device}/RESULT")).await?;
+        self.command_with_reply(device, command, payload, "RESULT", command)
+            .await
+    }
+
+    /// Send a command and wait for the matching reply
+    ///
+    /// Only one command is in flight per device at a time, and only replies containing `reply_key`
+    /// as top level key are accepted, so concurrent commands can't receive each other's replies.
+    ///
+    /// Failures like `{"Command":"Unknown"}` don't name the command they belong to, so a failure is only reported
+    /// if no matching reply follows shortly after it. A failing command sent to the same device by another client
+    /// at the same time can still be reported as the failure of this command.
+    async fn command_with_reply<T: DeserializeOwned>(
+        &self,
+        device: &str,
+        command: &str,
+        payload: &str,
+        reply_topic: &str,
+        reply_key: &str,
+    ) -> Result<T> {
+        let _guard = self.command_lock(device).await;
+
+        let topics = self.topic_scheme(device);
+        let mut rx = self
+            .mqtt
+            // the first matching reply is the one we want, so drop newer messages if we can't keep up
+            .subscribe(
+                topics.topic(TopicPrefix::Stat, device, reply_topic),
+                10,
+                OverflowPolicy::DropNewest,
+            )
+            .await?;
         self.mqtt
-            .send_str(&format!("cmnd/{device}/{command}"), payload)
+            .send_str(
+                &topics.topic(TopicPrefix::Command, device, command),
+                payload,
+            )
             .await?;
 
-        let reply = async {
-            while let Some(msg) = rx.recv().await {
-                if let Ok(response) = serde_json::from_slice(msg.payload.as_ref()) {
-                    return Ok(response);
+        let mut deadline = Instant::now() + self.timeout;
+        let mut failure = None;
+        loop {
+            let msg = match timeout_at(deadline, rx.recv()).await {
+                Ok(msg) => msg?,
+                Err(_) => {
+                    return Err(match failure {
+                        Some(status) => Error::CommandFailed(command.into(), status),
+                        None => Error::Timeout,
+                    })
+                }
+            };
+            let Ok(response) = serde_json::from_slice::<Map<String, Value>>(msg.payload.as_ref())
+            else {
+                continue;
+            };
+            if let Some(Value::String(status)) = response.get("Command") {
+                if failure.is_none() {
+                    debug!(status, "command failed, waiting for other replies");
+                    failure = Some(status.clone());
+                    deadline = deadline.min(Instant::now() + COMMAND_FAILURE_GRACE);
                 }
+                continue;
             }
+            if !is_reply_for(reply_key, &response) {
+                debug!(reply = ?response, "ignoring reply for different command");
+                continue;
+            }
+            return Ok(serde_json::from_value(Value::Object(response))?);
+        }
+    }
 
-            Err(MqttError::Eof.into())
+    /// Subscribe to the `INFO1` message a device publishes after booting, for [`wait_restarted`](Self::wait_restarted)
+    async fn boot_subscription(&self, topics: &TopicScheme, device: &str) -> Result<Subscription> {
+        self.mqtt
+            .subscribe(
+                topics.topic(TopicPrefix::Tele, device, "INFO1"),
+                1,
+                OverflowPolicy::DropOldest,
+            )
+            .await
+    }
+
+    /// Wait until a restarted device is back online
+    ///
+    /// The restart is detected by the device going offline or by the `INFO1` message a device publishes after booting.
+    /// Both receivers have to be subscribed before the restart is triggered, to not miss the device coming back.
+    async fn wait_restarted(
+        &self,
+        device: &str,
+        device_update: &mut BroadcastReceiver<DeviceUpdate>,
+        boot: &mut Subscription,
+        deadline: Instant,
+    ) -> Result<()> {
+        // the boot message is only sent once the device is back online
+        let offline = async {
+            loop {
+                let update = select! {
+                    update = device_update.recv() => update,
+                    boot = boot.recv() => {
+                        boot?;
+                        return Ok(false);
+                    }
+                    _ = self.mqtt.cancelled() => return Err(MqttError::Shutdown),
+                };
+                match update {
+                    Ok(DeviceUpdate::Removed(removed)) if removed == device => return Ok(true),
+                    // the missed updates might include the device going offline
+                    Err(RecvError::Lagged(_)) if !self.is_known(device) => return Ok(true),
+                    Err(RecvError::Closed) => return Err(MqttError::Eof),
+                    _ => {}
+                }
+            }
         };
+        let offline = timeout_at(deadline, offline)
+            .await
+            .map_err(|_| Error::Timeout)??;
+        if offline {
+            self.wait_online(device, device_update, deadline).await?;
+        }
+        Ok(())
+    }
 
-        timeout(self.timeout, reply)
+    /// Wait until a device that went offline is reported online by discovery again
+    async fn wait_online(
+        &self,
+        device: &str,
+        device_update: &mut BroadcastReceiver<DeviceUpdate>,
+        deadline: Instant,
+    ) -> Result<()> {
+        let online = async {
+            loop {
+                let update = select! {
+                    update = device_update.recv() => update,
+                    _ = self.mqtt.cancelled() => return Err(MqttError::Shutdown),
+                };
+                match update {
+                    Ok(DeviceUpdate::Added(added)) if added.topic == device => return Ok(()),
+                    // the missed updates might include the device coming back
+                    Err(RecvError::Lagged(_)) if self.is_known(device) => return Ok(()),
+                    Err(RecvError::Closed) => return Err(MqttError::Eof),
+                    _ => {}
+                }
+            }
+        };
+        Ok(timeout_at(deadline, online)
             .await
-            .map_err(|_| Error::Timeout)?
+            .map_err(|_| Error::Timeout)??)
+    }
+
+    /// Whether discovery currently lists the device as online
+    fn is_known(&self, device: &str) -> bool {
+        self.known_devices.lock().unwrap().contains_key(device)
+    }
+
+    /// Lock that has to be held while a command for the device is in flight
+    async fn command_lock(&self, device: &str) -> CommandGuard<'_> {
+        CommandGuard::acquire(&self.command_locks, device).await
+    }
+
+    /// Send a typed command and wait for its reply
+    ///
+    /// See the [`commands`] module for the available commands.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// # use tasmota_mqtt_client::commands::{Power, PowerState};
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let response = client.execute("tasmota_device", &Power::set(1, PowerState::Off)).await?;
+    /// println!("power: {}", response.0);
+    ///     # Ok(())
+    /// # }
+    /// ```
+    #[tracing::instrument(skip(self, command), fields(command = %command.name()))]
+    pub async fn execute<C: TasmotaCommand>(
+        &self,
+        device: &str,
+        command: &C,
+    ) -> Result<C::Response> {
+        if !command.expects_reply() {
+            self.mqtt
+                .send_str(
+                    &self
+                        .topic_scheme(device)
+                        .topic(TopicPrefix::Command, device, &command.name()),
+                    &command.payload(),
+                )
+                .await?;
+            return Ok(serde_json::from_value(Value::Null)?);
+        }
+        self.command_with_reply(
+            device,
+            &command.name(),
+            &command.payload(),
+            &command.reply_topic(),
+            &command.reply_key(),
+        )
+        .await
     }
 
     /// Get the ip address for the device
@@ -300,4 +925,126 @@ impl TasmotaClient {
         let response: NameResponse = self.command(device, "DeviceName", "").await?;
         Ok(response.device_name)
     }
+
+    /// Get the firmware version running on the device
+    #[tracing::instrument(skip(self))]
+    pub async fn firmware_version(&self, device: &str) -> Result<String> {
+        #[derive(Deserialize, Debug)]
+        struct StatusFirmwareResponse {
+            #[serde(rename = "StatusFWR")]
+            firmware: FirmwareResponse,
+        }
+        #[derive(Deserialize, Debug)]
+        struct FirmwareResponse {
+            #[serde(rename = "Version")]
+            version: String,
+        }
+        let response: StatusFirmwareResponse = self
+            .command_with_reply(device, "Status", "2", "STATUS2", "StatusFWR")
+            .await?;
+        Ok(response.firmware.version)
+    }
+}
+
+/// Check if a reply belongs to a command
+///
+/// Tasmota replies with the command name as top level key, but the case doesn't always match and an
+/// index of 1 might be omitted or added (`Power` can be answered with `POWER1` and `Power1` with `POWER`).
+fn is_reply_for(command: &str, reply: &Map<String, Value>) -> bool {
+    let (command, command_index) = split_index(command);
+    let command_index = if command_index.is_empty() {
+        "1"
+    } else {
+        command_index
+    };
+    reply.keys().any(|key| {
+        let (key, key_index) = split_index(key);
+        let key_index = if key_index.is_empty() { "1" } else { key_index };
+        key.eq_ignore_ascii_case(command) && command_index == key_index
+    })
+}
+
+/// Split a command name like `Power1` into the name and index
+fn split_index(name: &str) -> (&str, &str) {
+    let base = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    name.split_at(base.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    fn reply(json: &str) -> Map<String, Value> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn reply_matching() {
+        assert!(is_reply_for("Power", &reply(r#"{"POWER":"ON"}"#)));
+        assert!(is_reply_for("Power", &reply(r#"{"POWER1":"ON"}"#)));
+        assert!(is_reply_for("Power1", &reply(r#"{"POWER":"ON"}"#)));
+        assert!(is_reply_for("Power2", &reply(r#"{"POWER2":"OFF"}"#)));
+        assert!(is_reply_for("dimmer", &reply(r#"{"Dimmer":50}"#)));
+        assert!(is_reply_for("Timer1", &reply(r#"{"Timer1":{"Enable":1}}"#)));
+    }
+
+    #[test]
+    fn reply_mismatch() {
+        assert!(!is_reply_for("Power2", &reply(r#"{"POWER1":"ON"}"#)));
+        assert!(!is_reply_for("Power2", &reply(r#"{"POWER":"ON"}"#)));
+        assert!(!is_reply_for("Power", &reply(r#"{"POWER2":"ON"}"#)));
+        assert!(!is_reply_for("Timer1", &reply(r#"{"Timers":"ON"}"#)));
+        assert!(!is_reply_for(
+            "Timers",
+            &reply(r#"{"Timer1":{"Enable":1}}"#)
+        ));
+        assert!(!is_reply_for("Power", &reply(r#"{"Dimmer":50}"#)));
+    }
+
+    #[test]
+    fn split_command_index() {
+        assert_eq!(split_index("Power12"), ("Power", "12"));
+        assert_eq!(split_index("Power"), ("Power", ""));
+        assert_eq!(split_index("42"), ("", "42"));
+    }
+
+    #[tokio::test]
+    async fn command_locks_are_removed_after_use() {
+        let locks = DashMap::new();
+        let guard = CommandGuard::acquire(&locks, "device").await;
+        assert!(locks.contains_key("device"));
+        drop(guard);
+        assert!(locks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn command_locks_are_kept_while_waiting() {
+        let locks = DashMap::new();
+        let guard = CommandGuard::acquire(&locks, "device").await;
+        let mut waiting = pin!(CommandGuard::acquire(&locks, "device"));
+        assert!(timeout(Duration::from_millis(10), waiting.as_mut())
+            .await
+            .is_err());
+
+        drop(guard);
+        assert!(locks.contains_key("device"));
+        let guard = waiting.await;
+        assert!(locks.contains_key("device"));
+        drop(guard);
+        assert!(locks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn command_locks_are_removed_after_cancelled_wait() {
+        let locks = DashMap::new();
+        let guard = CommandGuard::acquire(&locks, "device").await;
+        let waiting = timeout(
+            Duration::from_millis(10),
+            CommandGuard::acquire(&locks, "device"),
+        );
+        assert!(waiting.await.is_err());
+        drop(guard);
+        assert!(locks.is_empty());
+    }
 }
@@ -0,0 +1,507 @@
+//! Control of dimmers, color temperature and RGB lights
+
+use crate::commands::{on_off, PowerState, SetOption, TasmotaCommand};
+use crate::status::power_states;
+use crate::{Result, TasmotaClient};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Error returned when parsing a color fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseColorError(String);
+
+impl Display for ParseColorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid color {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
+/// Red, green and blue components of a color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    pub fn new(red: u8, green: u8, blue: u8) -> Self {
+        Rgb { red, green, blue }
+    }
+}
+
+/// The color channels of a light
+///
+/// Formatted as hex string like `FF8000` when sending it to the device, parsed from both hex
+/// and the comma separated decimal format used when `SetOption17` is enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightColor {
+    Rgb(Rgb),
+    /// Color with a separate white channel
+    Rgbw(Rgb, u8),
+    /// Color with separate cold and warm white channels
+    Rgbcw(Rgb, u8, u8),
+    /// Lights without color, with one or two white channels
+    Channels(Vec<u8>),
+}
+
+impl LightColor {
+    fn channels(&self) -> Vec<u8> {
+        match self {
+            LightColor::Rgb(rgb) => vec![rgb.red, rgb.green, rgb.blue],
+            LightColor::Rgbw(rgb, white) => vec![rgb.red, rgb.green, rgb.blue, *white],
+            LightColor::Rgbcw(rgb, cold, warm) => {
+                vec![rgb.red, rgb.green, rgb.blue, *cold, *warm]
+            }
+            LightColor::Channels(channels) => channels.clone(),
+        }
+    }
+}
+
+impl From<Rgb> for LightColor {
+    fn from(rgb: Rgb) -> Self {
+        LightColor::Rgb(rgb)
+    }
+}
+
+impl FromStr for LightColor {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseColorError(s.into());
+        let channels = if s.contains(',') {
+            s.split(',')
+                .map(|channel| channel.trim().parse().map_err(|_| error()))
+                .collect::<Result<Vec<u8>, _>>()?
+        } else {
+            hex::decode(s.trim_start_matches('#')).map_err(|_| error())?
+        };
+        Ok(match channels[..] {
+            [red, green, blue] => LightColor::Rgb(Rgb { red, green, blue }),
+            [red, green, blue, white] => LightColor::Rgbw(Rgb { red, green, blue }, white),
+            [red, green, blue, cold, warm] => {
+                LightColor::Rgbcw(Rgb { red, green, blue }, cold, warm)
+            }
+            [_] | [_, _] => LightColor::Channels(channels),
+            _ => return Err(error()),
+        })
+    }
+}
+
+impl Display for LightColor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&hex::encode_upper(self.channels()))
+    }
+}
+
+impl<'de> Deserialize<'de> for LightColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <Cow<str>>::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// A color as hue, saturation and brightness
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Hsb {
+    /// Hue in degrees, from 0 to 360
+    pub hue: u16,
+    /// Saturation in percent
+    pub saturation: u8,
+    /// Brightness in percent
+    pub brightness: u8,
+}
+
+impl FromStr for Hsb {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseColorError(s.into());
+        let mut parts = s.split(',').map(str::trim);
+        let (Some(hue), Some(saturation), Some(brightness), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(error());
+        };
+        Ok(Hsb {
+            hue: hue.parse().map_err(|_| error())?,
+            saturation: saturation.parse().map_err(|_| error())?,
+            brightness: brightness.parse().map_err(|_| error())?,
+        })
+    }
+}
+
+impl Display for Hsb {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{}", self.hue, self.saturation, self.brightness)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hsb {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <Cow<str>>::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Color temperature of a white light
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(transparent)]
+pub struct ColorTemperature(u16);
+
+impl ColorTemperature {
+    /// Coldest color temperature supported by tasmota, about 6500K
+    pub const COLDEST: ColorTemperature = ColorTemperature(153);
+    /// Warmest color temperature supported by tasmota, about 2000K
+    pub const WARMEST: ColorTemperature = ColorTemperature(500);
+
+    /// Create a color temperature from mireds, clamped to the range supported by tasmota
+    pub fn from_mireds(mireds: u16) -> Self {
+        ColorTemperature(mireds.clamp(Self::COLDEST.0, Self::WARMEST.0))
+    }
+
+    /// Create a color temperature from kelvin, clamped to the range supported by tasmota
+    pub fn from_kelvin(kelvin: u32) -> Self {
+        let mireds = 1_000_000 / kelvin.max(1);
+        Self::from_mireds(mireds.min(u16::MAX as u32) as u16)
+    }
+
+    pub fn mireds(&self) -> u16 {
+        self.0
+    }
+
+    pub fn kelvin(&self) -> u32 {
+        1_000_000 / self.0 as u32
+    }
+}
+
+/// The combined state of a light, as replied to every light command
+///
+/// Only the values supported by the light are set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct LightState {
+    /// Brightness in percent
+    pub dimmer: Option<u8>,
+    /// Brightness of the color channels in percent, when color and white are split
+    #[serde(rename = "Dimmer1")]
+    pub dimmer_color: Option<u8>,
+    /// Brightness of the white channels in percent, when color and white are split
+    #[serde(rename = "Dimmer2")]
+    pub dimmer_white: Option<u8>,
+    pub color: Option<LightColor>,
+    #[serde(rename = "HSBColor")]
+    pub hsb_color: Option<Hsb>,
+    /// Brightness of the white channel in percent
+    pub white: Option<u8>,
+    #[serde(rename = "CT")]
+    pub ct: Option<ColorTemperature>,
+    /// Value of the individual channels in percent
+    pub channel: Vec<u8>,
+    #[serde(deserialize_with = "optional_on_off")]
+    pub fade: Option<bool>,
+    pub speed: Option<u8>,
+    pub scheme: Option<u8>,
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+impl LightState {
+    /// State of the relays controlling the light, by relay index starting at 1
+    pub fn power_states(&self) -> BTreeMap<u8, PowerState> {
+        power_states(&self.other)
+    }
+}
+
+fn optional_on_off<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    on_off(deserializer).map(Some)
+}
+
+/// How the channels of a light are controlled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightMode {
+    /// All channels are controlled as a single light
+    Combined,
+    /// Color and white channels are controlled as separate lights, `SetOption37` 128 or higher
+    SplitColorWhite,
+    /// Every channel is controlled as a separate light, `SetOption68` enabled
+    MultiChannel,
+}
+
+/// Change a setting of a light
+#[derive(Debug, Clone)]
+pub enum LightSetting {
+    /// Set the brightness in percent
+    Dimmer(u8),
+    /// Set the brightness of the color channels, when color and white are split
+    DimmerColor(u8),
+    /// Set the brightness of the white channels, when color and white are split
+    DimmerWhite(u8),
+    Ct(ColorTemperature),
+    Color(LightColor),
+    HsbColor(Hsb),
+    /// Set the brightness of the white channel in percent
+    White(u8),
+    /// Enable or disable fading between states
+    Fade(bool),
+    /// Set the fade speed, from 1 (fast) to 40 (slow)
+    Speed(u8),
+    /// Set the light scheme, like `0` for a single color or `2` for cycling colors
+    Scheme(u8),
+    /// Set the value of a single channel in percent, channels are numbered from 1
+    Channel(u8, u8),
+}
+
+impl TasmotaCommand for LightSetting {
+    type Response = LightState;
+
+    fn name(&self) -> Cow<'_, str> {
+        match self {
+            LightSetting::Dimmer(_) => "Dimmer".into(),
+            LightSetting::DimmerColor(_) => "Dimmer1".into(),
+            LightSetting::DimmerWhite(_) => "Dimmer2".into(),
+            LightSetting::Ct(_) => "CT".into(),
+            LightSetting::Color(_) => "Color".into(),
+            LightSetting::HsbColor(_) => "HSBColor".into(),
+            LightSetting::White(_) => "White".into(),
+            LightSetting::Fade(_) => "Fade".into(),
+            LightSetting::Speed(_) => "Speed".into(),
+            LightSetting::Scheme(_) => "Scheme".into(),
+            LightSetting::Channel(index, _) => format!("Channel{index}").into(),
+        }
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        match self {
+            LightSetting::Dimmer(value)
+            | LightSetting::DimmerColor(value)
+            | LightSetting::DimmerWhite(value)
+            | LightSetting::White(value)
+            | LightSetting::Speed(value)
+            | LightSetting::Scheme(value)
+            | LightSetting::Channel(_, value) => value.to_string(),
+            LightSetting::Ct(ct) => ct.mireds().to_string(),
+            LightSetting::Color(color) => color.to_string(),
+            LightSetting::HsbColor(hsb) => hsb.to_string(),
+            LightSetting::Fade(fade) => if *fade { "ON" } else { "OFF" }.to_string(),
+        }
+        .into()
+    }
+}
+
+/// Request the full state of a device, used to read the light state
+#[derive(Debug, Clone)]
+struct State;
+
+impl TasmotaCommand for State {
+    type Response = LightState;
+
+    fn name(&self) -> Cow<'_, str> {
+        "State".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        "".into()
+    }
+
+    fn reply_key(&self) -> Cow<'_, str> {
+        // the reply doesn't contain the command name, but always contains the uptime
+        "UptimeSec".into()
+    }
+}
+
+impl TasmotaClient {
+    /// Get the combined state of a light
+    #[tracing::instrument(skip(self))]
+    pub async fn light_state(&self, device: &str) -> Result<LightState> {
+        self.execute(device, &State).await
+    }
+
+    /// Change a setting of a light, returning the combined state of the light after the change
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// # use tasmota_mqtt_client::light::{ColorTemperature, LightSetting};
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let ct = ColorTemperature::from_kelvin(2700);
+    /// let state = client.set_light("tasmota_bulb", LightSetting::Ct(ct)).await?;
+    /// println!("brightness: {:?}", state.dimmer);
+    ///     # Ok(())
+    /// # }
+    /// ```
+    #[tracing::instrument(skip(self))]
+    pub async fn set_light(&self, device: &str, setting: LightSetting) -> Result<LightState> {
+        self.execute(device, &setting).await
+    }
+
+    /// Get how the channels of a light are controlled, based on `SetOption68` and `SetOption37`
+    #[tracing::instrument(skip(self))]
+    pub async fn light_mode(&self, device: &str) -> Result<LightMode> {
+        let multi_channel = self
+            .execute(
+                device,
+                &SetOption {
+                    option: 68,
+                    value: None,
+                },
+            )
+            .await?
+            .0
+             .0;
+        if multi_channel != 0 {
+            return Ok(LightMode::MultiChannel);
+        }
+        let remap = self
+            .execute(
+                device,
+                &SetOption {
+                    option: 37,
+                    value: None,
+                },
+            )
+            .await?
+            .0
+             .0;
+        Ok(if remap >= 128 {
+            LightMode::SplitColorWhite
+        } else {
+            LightMode::Combined
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_colors() {
+        assert_eq!("FF8000".parse(), Ok(LightColor::Rgb(Rgb::new(255, 128, 0))));
+        assert_eq!(
+            "#ff800010".parse(),
+            Ok(LightColor::Rgbw(Rgb::new(255, 128, 0), 16))
+        );
+        assert_eq!(
+            "0000002040".parse(),
+            Ok(LightColor::Rgbcw(Rgb::new(0, 0, 0), 32, 64))
+        );
+        assert_eq!("80".parse(), Ok(LightColor::Channels(vec![128])));
+        assert_eq!("8040".parse(), Ok(LightColor::Channels(vec![128, 64])));
+        assert!("".parse::<LightColor>().is_err());
+        assert!("FF800".parse::<LightColor>().is_err());
+        assert!("FF800000000000".parse::<LightColor>().is_err());
+        assert!("GG8000".parse::<LightColor>().is_err());
+    }
+
+    #[test]
+    fn parse_decimal_colors() {
+        assert_eq!(
+            "255,128,0".parse(),
+            Ok(LightColor::Rgb(Rgb::new(255, 128, 0)))
+        );
+        assert_eq!(
+            "255, 128, 0, 16".parse(),
+            Ok(LightColor::Rgbw(Rgb::new(255, 128, 0), 16))
+        );
+        assert!("256,0,0".parse::<LightColor>().is_err());
+        assert!("255,,0".parse::<LightColor>().is_err());
+    }
+
+    #[test]
+    fn format_colors() {
+        assert_eq!(LightColor::Rgb(Rgb::new(255, 128, 0)).to_string(), "FF8000");
+        assert_eq!(
+            LightColor::Rgbcw(Rgb::new(0, 0, 0), 32, 64).to_string(),
+            "0000002040"
+        );
+        assert_eq!(LightColor::Channels(vec![128]).to_string(), "80");
+    }
+
+    #[test]
+    fn parse_hsb() {
+        assert_eq!(
+            "30,100,50".parse(),
+            Ok(Hsb {
+                hue: 30,
+                saturation: 100,
+                brightness: 50
+            })
+        );
+        assert_eq!("360, 0, 100".parse::<Hsb>().unwrap().hue, 360);
+        assert!("30,100".parse::<Hsb>().is_err());
+        assert!("30,100,50,1".parse::<Hsb>().is_err());
+        assert!("30,-1,50".parse::<Hsb>().is_err());
+        assert_eq!(
+            Hsb {
+                hue: 30,
+                saturation: 100,
+                brightness: 50
+            }
+            .to_string(),
+            "30,100,50"
+        );
+    }
+
+    #[test]
+    fn color_temperature_conversion() {
+        assert_eq!(ColorTemperature::from_kelvin(4000).mireds(), 250);
+        assert_eq!(ColorTemperature::from_mireds(250).kelvin(), 4000);
+        assert_eq!(ColorTemperature::from_mireds(326).kelvin(), 3067);
+    }
+
+    #[test]
+    fn clamp_color_temperature() {
+        assert_eq!(
+            ColorTemperature::from_mireds(100),
+            ColorTemperature::COLDEST
+        );
+        assert_eq!(ColorTemperature::from_mireds(153).mireds(), 153);
+        assert_eq!(ColorTemperature::from_mireds(500).mireds(), 500);
+        assert_eq!(
+            ColorTemperature::from_mireds(600),
+            ColorTemperature::WARMEST
+        );
+
+        assert_eq!(ColorTemperature::from_kelvin(10_000).mireds(), 153);
+        assert_eq!(ColorTemperature::from_kelvin(1000).mireds(), 500);
+        assert_eq!(ColorTemperature::from_kelvin(0).mireds(), 500);
+        assert_eq!(ColorTemperature::COLDEST.kelvin(), 6535);
+        assert_eq!(ColorTemperature::WARMEST.kelvin(), 2000);
+    }
+
+    #[test]
+    fn parse_light_state() {
+        let state: LightState = serde_json::from_str(
+            r#"{"POWER":"ON","Dimmer":60,"Color":"99731F0000","HSBColor":"43,80,60","White":0,"CT":326,"Channel":[60,45,12,0,0],"Fade":"OFF","Speed":1,"Scheme":0}"#,
+        )
+        .unwrap();
+        assert_eq!(state.dimmer, Some(60));
+        assert_eq!(
+            state.color,
+            Some(LightColor::Rgbcw(Rgb::new(0x99, 0x73, 0x1F), 0, 0))
+        );
+        assert_eq!(state.hsb_color.unwrap().hue, 43);
+        assert_eq!(state.ct, Some(ColorTemperature::from_mireds(326)));
+        assert_eq!(state.channel, [60, 45, 12, 0, 0]);
+        assert_eq!(state.fade, Some(false));
+        assert_eq!(state.power_states()[&1], PowerState::On);
+    }
+}
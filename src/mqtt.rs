@@ -1,64 +1,500 @@
+use crate::error::MqttError;
+use crate::trie::TopicTrie;
 use crate::Result;
-use async_stream::try_stream;
-use rumqttc::{matches, AsyncClient, Event, EventLoop, MqttOptions, Packet, Publish, QoS};
+use rumqttc::{
+    AsyncClient, ConnectionError, Event, MqttOptions, Outgoing, Packet, Publish, QoS, SubAck,
+    SubscribeReasonCode,
+};
 use serde::Serialize;
-use std::pin::pin;
-use std::sync::Arc;
-use tokio::spawn;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::sync::Mutex;
-use tokio_stream::{Stream, StreamExt};
-use tracing::{debug, error};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch, Mutex, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, timeout_at, Instant};
+use tokio::{select, spawn};
+use tracing::{debug, error, info};
 
+/// Delay before the first reconnect attempt, doubled after every failed attempt
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// Maximum delay between reconnect attempts
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// Maximum time to wait for the disconnect packet to be sent when shutting down
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Maximum time to wait for the broker to acknowledge a subscription
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
 pub struct MqttHelper {
     client: AsyncClient,
-    #[allow(clippy::type_complexity)]
-    listeners: Arc<Mutex<Vec<(String, Sender<Publish>)>>>,
+    listeners: Arc<StdMutex<Listeners>>,
+    subscriptions: Arc<StdMutex<PendingSubscriptions>>,
+    subscribe_lock: Arc<Mutex<()>>,
+    unsubscribe: mpsc::UnboundedSender<String>,
+    state: watch::Receiver<ConnectionState>,
+    shutdown: Arc<watch::Sender<bool>>,
+}
+
+/// State of the connection to the MQTT server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Trying to connect to the server
+    Connecting,
+    /// Connected to the server
+    Connected,
+    /// The connection was lost or couldn't be established, a reconnect will be attempted
+    Disconnected(String),
+}
+
+/// What to do when a subscriber doesn't keep up with incoming messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued message to make room for the new one
+    DropOldest,
+    /// Discard the new message
+    DropNewest,
+    /// Stop delivering messages to the subscriber
+    Disconnect,
+}
+
+/// A message or notification delivered to a subscriber
+#[derive(Debug)]
+pub enum Delivery {
+    Message(Publish),
+    /// Messages have been dropped because the subscriber didn't keep up
+    Lagged(u64),
+}
+
+/// Messages queued for a single subscriber
+///
+/// Pushing never waits for the subscriber, so a slow subscriber can't block delivery to other subscribers.
+struct Queue {
+    state: StdMutex<QueueState>,
+    notify: Notify,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+#[derive(Default)]
+struct QueueState {
+    messages: VecDeque<Publish>,
+    lagged: u64,
+    overflowed: bool,
+}
+
+impl Queue {
+    fn push(&self, message: &Publish) {
+        let mut state = self.state.lock().unwrap();
+        if state.overflowed {
+            return;
+        }
+        if state.messages.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::DropOldest => {
+                    state.messages.pop_front();
+                    state.lagged += 1;
+                }
+                OverflowPolicy::DropNewest => {
+                    state.lagged += 1;
+                    drop(state);
+                    self.notify.notify_one();
+                    return;
+                }
+                OverflowPolicy::Disconnect => {
+                    state.overflowed = true;
+                    drop(state);
+                    self.notify.notify_one();
+                    return;
+                }
+            }
+        }
+        state.messages.push_back(message.clone());
+        drop(state);
+        self.notify.notify_one();
+    }
+}
+
+/// Active listeners and the number of listeners for every subscribed filter
+#[derive(Default)]
+struct Listeners {
+    trie: TopicTrie<Arc<Queue>>,
+    filters: HashMap<String, usize>,
+    next_id: u64,
+}
+
+impl Listeners {
+    fn add(&mut self, filter: &str, queue: Arc<Queue>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.trie.insert(filter, id, queue);
+        *self.filters.entry(filter.into()).or_default() += 1;
+        id
+    }
+
+    /// Remove a listener, returns true if this was the last listener for the filter
+    fn remove(&mut self, filter: &str, id: u64) -> bool {
+        if self.trie.remove(filter, id).is_none() {
+            return false;
+        }
+        let Some(count) = self.filters.get_mut(filter) else {
+            return false;
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.filters.remove(filter);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Messages received for a subscribed topic filter
+///
+/// The broker subscription is removed once the last subscription for a filter is dropped.
+pub struct Subscription {
+    queue: Arc<Queue>,
+    filter: String,
+    id: u64,
+    listeners: Arc<StdMutex<Listeners>>,
+    unsubscribe: mpsc::UnboundedSender<String>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl Subscription {
+    /// Receive the next message or lag notification
+    pub async fn next_delivery(&mut self) -> Result<Delivery, MqttError> {
+        loop {
+            if *self.shutdown.borrow() {
+                return Err(MqttError::Shutdown);
+            }
+            {
+                let mut state = self.queue.state.lock().unwrap();
+                if state.lagged > 0 {
+                    return Ok(Delivery::Lagged(std::mem::take(&mut state.lagged)));
+                }
+                if let Some(message) = state.messages.pop_front() {
+                    return Ok(Delivery::Message(message));
+                }
+                if state.overflowed {
+                    return Err(MqttError::Overflow);
+                }
+            }
+            select! {
+                _ = self.queue.notify.notified() => {}
+                changed = self.shutdown.changed() => {
+                    if changed.is_err() {
+                        return Err(MqttError::Shutdown);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Receive the next message, skipping over lag notifications
+    pub async fn recv(&mut self) -> Result<Publish, MqttError> {
+        loop {
+            match self.next_delivery().await? {
+                Delivery::Message(message) => return Ok(message),
+                Delivery::Lagged(count) => {
+                    debug!(filter = self.filter, count, "subscriber lagged behind");
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // the unsubscribe is sent by the event loop, which can wait for room in the request queue
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners.remove(&self.filter, self.id) && !*self.shutdown.borrow() {
+            let _ = self.unsubscribe.send(self.filter.clone());
+        }
+    }
+}
+
+/// Subscriptions waiting for the broker to acknowledge them
+///
+/// The packet id of a subscribe is only known once the event loop has sent it. To know which subscription
+/// an outgoing packet id belongs to, only one subscribe request is queued at a time, the next one is queued
+/// once the packet id of the previous one is known. Acknowledgements are then matched by their packet id.
+#[derive(Default)]
+struct PendingSubscriptions {
+    queued: Option<QueuedSubscription>,
+    /// Subscribe requests that were given up on before the event loop sent them
+    abandoned: usize,
+    sent: HashMap<u16, oneshot::Sender<bool>>,
+}
+
+struct QueuedSubscription {
+    /// Notified once the request has been sent
+    sent: oneshot::Sender<()>,
+    /// Notified with the result once the broker has acknowledged the request
+    ack: oneshot::Sender<bool>,
+}
+
+impl PendingSubscriptions {
+    fn sent(&mut self, pkid: u16) {
+        if self.abandoned > 0 {
+            // requests are sent in order, so this is the packet id of the oldest abandoned request
+            self.abandoned -= 1;
+            return;
+        }
+        if let Some(queued) = self.queued.take() {
+            let _ = queued.sent.send(());
+            self.sent.insert(pkid, queued.ack);
+        }
+    }
+
+    /// Give up on the queued request, the packet id it gets once it is sent belongs to nobody
+    fn abandon(&mut self) {
+        if self.queued.take().is_some() {
+            self.abandoned += 1;
+        }
+    }
+
+    fn acknowledged(&mut self, ack: SubAck) {
+        if let Some(tx) = self.sent.remove(&ack.pkid) {
+            let success = ack
+                .return_codes
+                .iter()
+                .all(|code| matches!(code, SubscribeReasonCode::Success(_)));
+            let _ = tx.send(success);
+        }
+    }
+
+    /// Subscriptions that were sent but not acknowledged won't be acknowledged after reconnecting
+    fn disconnected(&mut self) {
+        self.sent.clear();
+    }
+
+    fn clear(&mut self) {
+        self.queued = None;
+        self.abandoned = 0;
+        self.sent.clear();
+    }
 }
 
 impl MqttHelper {
-    pub fn connect(opts: MqttOptions) -> Self {
-        let (client, event_loop) = AsyncClient::new(opts, 10);
+    /// Start connecting to the MQTT server, returning the handle of the event loop task
+    pub fn connect(opts: MqttOptions) -> (Self, JoinHandle<()>) {
+        let (client, mut event_loop) = AsyncClient::new(opts, 10);
+        let (state_tx, state) = watch::channel(ConnectionState::Connecting);
+        let (unsubscribe, mut unsubscribe_rx) = mpsc::unbounded_channel();
+
+        let helper = Self {
+            client,
+            listeners: Arc::default(),
+            subscriptions: Arc::default(),
+            subscribe_lock: Arc::default(),
+            unsubscribe,
+            state,
+            shutdown: Arc::new(watch::channel(false).0),
+        };
+        let mqtt = helper.clone();
+
+        let task = spawn(async move {
+            let mut reconnect_delay = MIN_RECONNECT_DELAY;
+            let mut connected_before = false;
+            let mut unsubscribes = VecDeque::new();
 
-        let listeners = Arc::<Mutex<Vec<(String, Sender<_>)>>>::default();
-        let senders = listeners.clone();
+            loop {
+                // polling the event loop makes room in the request queue for unsubscribes that didn't fit before
+                mqtt.queue_unsubscribes(&mut unsubscribes);
+                let result = select! {
+                    result = event_loop.poll() => Some(result),
+                    Some(filter) = unsubscribe_rx.recv() => {
+                        unsubscribes.push_back(filter);
+                        continue;
+                    }
+                    _ = mqtt.cancelled() => None,
+                };
+                let Some(result) = result else {
+                    // give the event loop a chance to send the disconnect packet
+                    if *state_tx.borrow() == ConnectionState::Connected {
+                        let flush = async {
+                            loop {
+                                match event_loop.poll().await {
+                                    Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+                                    _ => {}
+                                }
+                            }
+                        };
+                        let _ = timeout(DISCONNECT_TIMEOUT, flush).await;
+                    }
+                    break;
+                };
 
-        spawn(async move {
-            let stream = event_loop_to_stream(event_loop);
-            let messages = stream
-                .filter_map(|event| match event {
+                let event = match result {
                     Ok(event) => {
                         debug!(event = ?event, "processing event");
-                        Some(event)
+                        event
                     }
+                    Err(ConnectionError::RequestsDone) => break,
                     Err(e) => {
                         error!(error = ?e, "error while receiving mqtt message");
-                        None
+                        mqtt.subscriptions.lock().unwrap().disconnected();
+                        state_tx.send_replace(ConnectionState::Disconnected(e.to_string()));
+                        select! {
+                            _ = sleep(reconnect_delay) => {}
+                            _ = mqtt.cancelled() => break,
+                        }
+                        reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                        state_tx.send_replace(ConnectionState::Connecting);
+                        continue;
+                    }
+                };
+
+                match event {
+                    Event::Incoming(Packet::Publish(message)) => {
+                        let listeners = mqtt.listeners.lock().unwrap();
+                        for queue in listeners.trie.matches(&message.topic) {
+                            queue.push(&message);
+                        }
+                    }
+                    Event::Incoming(Packet::ConnAck(ack)) => {
+                        reconnect_delay = MIN_RECONNECT_DELAY;
+                        // without a persisted session the broker has forgotten our subscriptions
+                        if connected_before && !ack.session_present {
+                            spawn(mqtt.clone().resubscribe());
+                        }
+                        connected_before = true;
+                        state_tx.send_replace(ConnectionState::Connected);
                     }
-                })
-                .filter_map(|event| match event {
-                    Event::Incoming(Packet::Publish(message)) => Some(message),
-                    _ => None,
-                });
-
-            let mut messages = pin!(messages);
-
-            while let Some(message) = messages.next().await {
-                let message: Publish = message;
-                let mut listeners_ref = senders.lock().await;
-                listeners_ref.retain(|(_, sender)| !sender.is_closed());
-                for (filter, sender) in listeners_ref.iter() {
-                    if matches(&message.topic, filter.as_str()) {
-                        let _ = sender.send(message.clone()).await;
+                    Event::Outgoing(Outgoing::Subscribe(pkid)) => {
+                        mqtt.subscriptions.lock().unwrap().sent(pkid);
                     }
+                    Event::Incoming(Packet::SubAck(ack)) => {
+                        mqtt.subscriptions.lock().unwrap().acknowledged(ack);
+                    }
+                    _ => {}
+                }
+            }
+
+            mqtt.subscriptions.lock().unwrap().clear();
+            state_tx.send_replace(ConnectionState::Disconnected("client shut down".into()));
+        });
+
+        (helper, task)
+    }
+
+    /// Stop the event loop and fail all pending and future operations with [`MqttError::Shutdown`]
+    ///
+    /// A disconnect packet is sent to the server if the client is connected.
+    pub fn shutdown(&self) {
+        if self.shutdown.send_replace(true) {
+            return;
+        }
+        if let Err(e) = self.client.try_disconnect() {
+            debug!(error = ?e, "failed to queue disconnect");
+        }
+    }
+
+    /// Wait until the client is shut down
+    pub fn cancelled(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut shutdown = self.shutdown.subscribe();
+        async move {
+            let _ = shutdown.wait_for(|shutdown| *shutdown).await;
+        }
+    }
+
+    fn is_shutdown(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Watch the state of the connection to the MQTT server
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Send unsubscribes for filters that no longer have any listeners, in the order the listeners were removed
+    ///
+    /// Unsubscribes that don't fit in the request queue are kept to be retried.
+    fn queue_unsubscribes(&self, pending: &mut VecDeque<String>) {
+        // keep the lock while queueing the unsubscribe, so it can't be reordered with a new subscription for the filter
+        let listeners = self.listeners.lock().unwrap();
+        while let Some(filter) = pending.front() {
+            if listeners.filters.contains_key(filter) {
+                // subscribed again in the meantime
+                pending.pop_front();
+                continue;
+            }
+            match self.client.try_unsubscribe(filter) {
+                Ok(()) => {
+                    debug!(filter, "unsubscribing");
+                    pending.pop_front();
                 }
+                Err(e) => {
+                    debug!(error = ?e, filter, "request queue is full, delaying unsubscribe");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Subscribe to all filters with active listeners again
+    async fn resubscribe(self) {
+        // restore in a stable order, so retained discovery configs are still received before the LWT messages
+        let filters: BTreeSet<String> = self
+            .listeners
+            .lock()
+            .unwrap()
+            .filters
+            .keys()
+            .cloned()
+            .collect();
+        info!(
+            count = filters.len(),
+            "restoring subscriptions after reconnect"
+        );
+
+        for filter in filters {
+            if !self.listeners.lock().unwrap().filters.contains_key(&filter) {
+                continue;
+            }
+            // nobody is waiting for the acknowledgement
+            let deadline = Instant::now() + SUBSCRIBE_TIMEOUT;
+            if let Err(e) = self.send_subscribe(&filter, deadline).await {
+                error!(error = ?e, filter, "failed to restore subscription");
             }
+        }
+    }
+
+    /// Queue a subscribe request and wait until the event loop has sent it, returning the receiver for the acknowledgement
+    async fn send_subscribe(
+        &self,
+        filter: &str,
+        deadline: Instant,
+    ) -> Result<oneshot::Receiver<bool>> {
+        let _order = self.subscribe_lock.lock().await;
+        let (sent_tx, sent_rx) = oneshot::channel();
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.subscriptions.lock().unwrap().queued = Some(QueuedSubscription {
+            sent: sent_tx,
+            ack: ack_tx,
         });
+        if let Err(e) = self.client.subscribe(filter, QoS::AtLeastOnce).await {
+            self.subscriptions.lock().unwrap().queued = None;
+            return Err(e.into());
+        }
 
-        Self { client, listeners }
+        match timeout_at(deadline, sent_rx).await {
+            Ok(Ok(())) => Ok(ack_rx),
+            Ok(Err(_)) if self.is_shutdown() => Err(MqttError::Shutdown.into()),
+            Ok(Err(_)) => Err(MqttError::Eof.into()),
+            Err(_) => {
+                self.subscriptions.lock().unwrap().abandon();
+                Err(MqttError::SubscribeTimeout(filter.into()).into())
+            }
+        }
     }
 
     pub async fn send<B: Serialize>(&self, topic: &str, body: &B) -> Result<()> {
+        if self.is_shutdown() {
+            return Err(MqttError::Shutdown.into());
+        }
         self.client
             .publish(topic, QoS::AtLeastOnce, false, serde_json::to_vec(body)?)
             .await?;
@@ -66,25 +502,223 @@ impl MqttHelper {
     }
 
     pub async fn send_str(&self, topic: &str, body: &str) -> Result<()> {
+        if self.is_shutdown() {
+            return Err(MqttError::Shutdown.into());
+        }
         self.client
             .publish(topic, QoS::AtLeastOnce, false, body)
             .await?;
         Ok(())
     }
 
-    pub async fn subscribe(&self, topic: String) -> Result<Receiver<Publish>> {
-        self.client.subscribe(&topic, QoS::AtLeastOnce).await?;
-        let (tx, rx) = channel(10);
-        self.listeners.lock().await.push((topic, tx));
-        Ok(rx)
+    pub async fn send_bytes(&self, topic: &str, body: Vec<u8>) -> Result<()> {
+        if self.is_shutdown() {
+            return Err(MqttError::Shutdown.into());
+        }
+        self.client
+            .publish(topic, QoS::AtLeastOnce, false, body)
+            .await?;
+        Ok(())
+    }
+
+    /// Subscribe to a topic, waiting until the broker has acknowledged the subscription
+    ///
+    /// Fails with [`MqttError::SubscribeTimeout`] if the subscription isn't acknowledged within ten seconds.
+    ///
+    /// Up to `capacity` messages are queued for the subscriber, after which the overflow policy is applied.
+    pub async fn subscribe(
+        &self,
+        topic: String,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> Result<Subscription> {
+        if self.is_shutdown() {
+            return Err(MqttError::Shutdown.into());
+        }
+        let queue = Arc::new(Queue {
+            state: StdMutex::default(),
+            notify: Notify::new(),
+            capacity,
+            policy,
+        });
+        // register the listener first so no retained messages are missed
+        let id = self.listeners.lock().unwrap().add(&topic, queue.clone());
+        let subscription = Subscription {
+            queue,
+            filter: topic.clone(),
+            id,
+            listeners: self.listeners.clone(),
+            unsubscribe: self.unsubscribe.clone(),
+            shutdown: self.shutdown.subscribe(),
+        };
+
+        let deadline = Instant::now() + SUBSCRIBE_TIMEOUT;
+        let ack = self.send_subscribe(&topic, deadline).await?;
+        match timeout_at(deadline, ack).await {
+            Ok(Ok(true)) => Ok(subscription),
+            Ok(Ok(false)) => Err(MqttError::SubscribeFailed(topic).into()),
+            Ok(Err(_)) if self.is_shutdown() => Err(MqttError::Shutdown.into()),
+            Ok(Err(_)) => Err(MqttError::Eof.into()),
+            Err(_) => Err(MqttError::SubscribeTimeout(topic).into()),
+        }
     }
 }
 
-fn event_loop_to_stream(mut event_loop: EventLoop) -> impl Stream<Item = Result<Event>> {
-    try_stream! {
-        loop {
-            let event = event_loop.poll().await?;
-            yield event;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscription(capacity: usize, policy: OverflowPolicy) -> Subscription {
+        let (unsubscribe, _) = mpsc::unbounded_channel();
+        Subscription {
+            queue: Arc::new(Queue {
+                state: StdMutex::default(),
+                notify: Notify::new(),
+                capacity,
+                policy,
+            }),
+            filter: "stat/+/RESULT".into(),
+            id: 0,
+            listeners: Arc::default(),
+            unsubscribe,
+            shutdown: watch::channel(false).1,
+        }
+    }
+
+    fn push(subscription: &Subscription, payloads: &[&str]) {
+        for payload in payloads {
+            let message = Publish::new("stat/lamp/RESULT", QoS::AtMostOnce, *payload);
+            subscription.queue.push(&message);
         }
     }
+
+    async fn next(subscription: &mut Subscription) -> String {
+        match subscription.next_delivery().await.unwrap() {
+            Delivery::Message(message) => String::from_utf8(message.payload.to_vec()).unwrap(),
+            Delivery::Lagged(count) => format!("lagged {count}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn deliver_within_capacity() {
+        let mut subscription = subscription(2, OverflowPolicy::Disconnect);
+        push(&subscription, &["1", "2"]);
+        assert_eq!(next(&mut subscription).await, "1");
+        assert_eq!(next(&mut subscription).await, "2");
+        push(&subscription, &["3"]);
+        assert_eq!(next(&mut subscription).await, "3");
+    }
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        let mut subscription = subscription(2, OverflowPolicy::DropOldest);
+        push(&subscription, &["1", "2", "3", "4"]);
+        assert_eq!(next(&mut subscription).await, "lagged 2");
+        assert_eq!(next(&mut subscription).await, "3");
+        assert_eq!(next(&mut subscription).await, "4");
+    }
+
+    #[tokio::test]
+    async fn drop_newest() {
+        let mut subscription = subscription(2, OverflowPolicy::DropNewest);
+        push(&subscription, &["1", "2", "3", "4"]);
+        assert_eq!(next(&mut subscription).await, "lagged 2");
+        assert_eq!(next(&mut subscription).await, "1");
+        assert_eq!(next(&mut subscription).await, "2");
+        // there is room again after the queue has been read
+        push(&subscription, &["5"]);
+        assert_eq!(next(&mut subscription).await, "5");
+    }
+
+    #[tokio::test]
+    async fn disconnect_on_overflow() {
+        let mut subscription = subscription(2, OverflowPolicy::Disconnect);
+        push(&subscription, &["1", "2", "3"]);
+        assert_eq!(next(&mut subscription).await, "1");
+        assert_eq!(next(&mut subscription).await, "2");
+        assert!(matches!(
+            subscription.next_delivery().await,
+            Err(MqttError::Overflow)
+        ));
+        // nothing is delivered after overflowing
+        push(&subscription, &["4"]);
+        assert!(matches!(
+            subscription.next_delivery().await,
+            Err(MqttError::Overflow)
+        ));
+    }
+
+    #[tokio::test]
+    async fn recv_skips_lag_notifications() {
+        let mut subscription = subscription(1, OverflowPolicy::DropOldest);
+        push(&subscription, &["1", "2"]);
+        let message = subscription.recv().await.unwrap();
+        assert_eq!(message.payload.as_ref(), b"2");
+    }
+
+    fn queue(
+        pending: &mut PendingSubscriptions,
+    ) -> (oneshot::Receiver<()>, oneshot::Receiver<bool>) {
+        let (sent_tx, sent_rx) = oneshot::channel();
+        let (ack_tx, ack_rx) = oneshot::channel();
+        pending.queued = Some(QueuedSubscription {
+            sent: sent_tx,
+            ack: ack_tx,
+        });
+        (sent_rx, ack_rx)
+    }
+
+    fn ack(pkid: u16, code: SubscribeReasonCode) -> SubAck {
+        SubAck::new(pkid, vec![code])
+    }
+
+    #[test]
+    fn match_acknowledgement_by_pkid() {
+        let mut pending = PendingSubscriptions::default();
+        let (mut first_sent, mut first_ack) = queue(&mut pending);
+        pending.sent(7);
+        assert_eq!(first_sent.try_recv(), Ok(()));
+        let (_, mut second_ack) = queue(&mut pending);
+        pending.sent(3);
+
+        pending.acknowledged(ack(3, SubscribeReasonCode::Failure));
+        assert_eq!(second_ack.try_recv(), Ok(false));
+        assert!(first_ack.try_recv().is_err());
+        pending.acknowledged(ack(7, SubscribeReasonCode::Success(QoS::AtLeastOnce)));
+        assert_eq!(first_ack.try_recv(), Ok(true));
+    }
+
+    #[test]
+    fn skip_abandoned_request() {
+        let mut pending = PendingSubscriptions::default();
+        let _ = queue(&mut pending);
+        pending.abandon();
+        let (mut sent, mut ack_rx) = queue(&mut pending);
+
+        // the abandoned request is sent first, its packet id must not be attributed to the queued request
+        pending.sent(1);
+        assert!(sent.try_recv().is_err());
+        pending.acknowledged(ack(1, SubscribeReasonCode::Success(QoS::AtLeastOnce)));
+        assert!(ack_rx.try_recv().is_err());
+
+        pending.sent(2);
+        assert_eq!(sent.try_recv(), Ok(()));
+        pending.acknowledged(ack(2, SubscribeReasonCode::Success(QoS::AtLeastOnce)));
+        assert_eq!(ack_rx.try_recv(), Ok(true));
+    }
+
+    #[test]
+    fn drop_unacknowledged_on_disconnect() {
+        let mut pending = PendingSubscriptions::default();
+        let (_, mut ack_rx) = queue(&mut pending);
+        pending.sent(1);
+        pending.disconnected();
+        assert_eq!(ack_rx.try_recv(), Err(oneshot::error::TryRecvError::Closed));
+
+        // a request that wasn't sent yet is still sent after reconnecting
+        let (mut sent, _) = queue(&mut pending);
+        pending.disconnected();
+        pending.sent(1);
+        assert_eq!(sent.try_recv(), Ok(()));
+    }
 }
@@ -0,0 +1,355 @@
+//! Relay control and power state events
+
+use crate::commands::{single_value, Power, PowerAction, PowerState, Status, TasmotaCommand};
+use crate::error::MqttError;
+use crate::mqtt::{Delivery, OverflowPolicy};
+use crate::status::{power_states, StatusState};
+use crate::{Result, TasmotaClient, TopicPrefix};
+use async_stream::try_stream;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use tokio_stream::Stream;
+use tracing::debug;
+
+/// Time in which the same state change received from a different topic is considered a duplicate
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// The state of a relay has changed or was reported
+///
+/// See also [`TasmotaClient::power_events`](crate::TasmotaClient::power_events).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowerEvent {
+    /// Topic of the device
+    pub device: String,
+    /// Index of the relay, starting at 1
+    pub index: u8,
+    pub state: PowerState,
+}
+
+/// Where a power state was received from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PowerSource {
+    /// The plain `ON` or `OFF` published to `stat/<topic>/POWER<index>`
+    Plain,
+    /// A json reply containing `POWER<index>` keys
+    Json,
+}
+
+/// Turn stat messages into power events
+///
+/// Tasmota reports every state change twice, as json reply and as plain state.
+/// Only the first of both is turned into an event.
+#[derive(Default)]
+struct PowerEvents {
+    last: HashMap<(String, u8), (PowerState, PowerSource, Instant)>,
+}
+
+impl PowerEvents {
+    pub fn handle(&mut self, device: &str, name: &str, payload: &[u8]) -> Vec<PowerEvent> {
+        let states = if let Ok(reply) = serde_json::from_slice::<Map<String, Value>>(payload) {
+            power_states(&reply)
+                .into_iter()
+                .map(|(index, state)| (index, state, PowerSource::Json))
+                .collect()
+        } else if let Some(index) = name.strip_prefix("POWER") {
+            let index = if index.is_empty() {
+                1
+            } else {
+                let Ok(index) = index.parse() else {
+                    return Vec::new();
+                };
+                index
+            };
+            let Ok(state) = PowerState::deserialize(Value::String(
+                String::from_utf8_lossy(payload).into_owned(),
+            )) else {
+                return Vec::new();
+            };
+            vec![(index, state, PowerSource::Plain)]
+        } else {
+            return Vec::new();
+        };
+
+        let now = Instant::now();
+        states
+            .into_iter()
+            .filter(|(index, state, source)| {
+                let previous = self
+                    .last
+                    .insert((device.into(), *index), (*state, *source, now));
+                !matches!(previous, Some((previous_state, previous_source, time))
+                    if previous_state == *state
+                        && previous_source != *source
+                        && now - time < DUPLICATE_WINDOW)
+            })
+            .map(|(index, state, _)| PowerEvent {
+                device: device.into(),
+                index,
+                state,
+            })
+            .collect()
+    }
+}
+
+/// Get or set the time after which a relay is automatically turned off again
+///
+/// Durations up to 11.1 seconds are rounded up to steps of 0.1 seconds, longer durations are rounded up to whole seconds.
+/// A duration of zero disables the automatic turn off.
+#[derive(Debug, Clone)]
+pub struct PulseTime {
+    /// Index of the relay, starting at 1
+    pub index: u8,
+    pub duration: Option<Duration>,
+}
+
+impl PulseTime {
+    /// Get the pulse time of a relay
+    pub fn get(index: u8) -> Self {
+        PulseTime {
+            index,
+            duration: None,
+        }
+    }
+
+    /// Set the pulse time of a relay
+    pub fn set(index: u8, duration: Duration) -> Self {
+        PulseTime {
+            index,
+            duration: Some(duration),
+        }
+    }
+}
+
+/// Convert a duration to the value used by tasmota
+fn encode_pulse_time(duration: Duration) -> u32 {
+    let millis = duration.as_millis();
+    if millis <= 11_100 {
+        ((millis + 99) / 100) as u32
+    } else {
+        // values from 112 are whole seconds, starting at 12 seconds
+        ((millis + 999) / 1000) as u32 + 100
+    }
+}
+
+/// Convert the value used by tasmota to a duration
+fn decode_pulse_time(value: u32) -> Duration {
+    if value < 112 {
+        Duration::from_millis(value as u64 * 100)
+    } else {
+        Duration::from_secs(value as u64 - 100)
+    }
+}
+
+/// Reply to [`PulseTime`]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct PulseTimeResponse(#[serde(deserialize_with = "single_value")] pub PulseTimeValue);
+
+/// Configured and remaining pulse time, in the encoding used by tasmota
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PulseTimeValue {
+    pub set: u32,
+    pub remaining: u32,
+}
+
+impl PulseTimeValue {
+    /// The configured pulse time
+    pub fn duration(&self) -> Duration {
+        decode_pulse_time(self.set)
+    }
+
+    /// The time left before the relay is turned off
+    pub fn remaining(&self) -> Duration {
+        decode_pulse_time(self.remaining)
+    }
+}
+
+impl TasmotaCommand for PulseTime {
+    type Response = PulseTimeResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        format!("PulseTime{}", self.index).into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.duration
+            .map(|duration| encode_pulse_time(duration).to_string())
+            .unwrap_or_default()
+            .into()
+    }
+}
+
+impl TasmotaClient {
+    /// Turn a relay on or off, returning the new state
+    ///
+    /// Relays are numbered starting at 1.
+    #[tracing::instrument(skip(self))]
+    pub async fn set_power(
+        &self,
+        device: &str,
+        index: u8,
+        state: PowerState,
+    ) -> Result<PowerState> {
+        Ok(self.execute(device, &Power::set(index, state)).await?.0)
+    }
+
+    /// Toggle a relay, returning the new state
+    #[tracing::instrument(skip(self))]
+    pub async fn toggle(&self, device: &str, index: u8) -> Result<PowerState> {
+        Ok(self
+            .execute(device, &Power::set(index, PowerAction::Toggle))
+            .await?
+            .0)
+    }
+
+    /// Start blinking a relay, the number of blinks is set by the `BlinkCount` setting of the device
+    #[tracing::instrument(skip(self))]
+    pub async fn blink(&self, device: &str, index: u8) -> Result<PowerState> {
+        Ok(self
+            .execute(device, &Power::set(index, PowerAction::Blink))
+            .await?
+            .0)
+    }
+
+    /// Get the state of all relays of a device, by relay index starting at 1
+    #[tracing::instrument(skip(self))]
+    pub async fn power_states(&self, device: &str) -> Result<BTreeMap<u8, PowerState>> {
+        let state = self.execute(device, &Status(11)).await?.0;
+        Ok(StatusState::deserialize(state)?.power_states())
+    }
+
+    /// Get the time after which a relay is automatically turned off again
+    #[tracing::instrument(skip(self))]
+    pub async fn pulse_time(&self, device: &str, index: u8) -> Result<Duration> {
+        Ok(self
+            .execute(device, &PulseTime::get(index))
+            .await?
+            .0
+            .duration())
+    }
+
+    /// Set the time after which a relay is automatically turned off again, zero disables the automatic turn off
+    ///
+    /// Returns the pulse time as stored by the device, which is rounded to what tasmota supports.
+    #[tracing::instrument(skip(self))]
+    pub async fn set_pulse_time(
+        &self,
+        device: &str,
+        index: u8,
+        duration: Duration,
+    ) -> Result<Duration> {
+        Ok(self
+            .execute(device, &PulseTime::set(index, duration))
+            .await?
+            .0
+            .duration())
+    }
+
+    /// Receive an event whenever a relay changes state
+    ///
+    /// This includes changes made by physical buttons, rules and other clients.
+    /// The filter is either the topic of a single device, or `+` to receive the events of all devices.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use std::pin::pin;
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// # use tokio_stream::StreamExt;
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let mut events = pin!(client.power_events("+"));
+    /// while let Some(event) = events.next().await {
+    ///     let event = event?;
+    ///     println!("relay {} of {} is now {}", event.index, event.device, event.state);
+    /// }
+    ///     # Ok(())
+    /// # }
+    /// ```
+    pub fn power_events<'a>(
+        &'a self,
+        device_filter: &'a str,
+    ) -> impl Stream<Item = Result<PowerEvent>> + 'a {
+        try_stream! {
+            let topics = self.topic_scheme(device_filter);
+            let mut rx = self
+                .mqtt
+                .subscribe(
+                    topics.topic(TopicPrefix::Stat, device_filter, "+"),
+                    100,
+                    OverflowPolicy::DropOldest,
+                )
+                .await?;
+            let mut events = PowerEvents::default();
+
+            loop {
+                let msg = match rx.next_delivery().await {
+                    Ok(Delivery::Message(msg)) => msg,
+                    Ok(Delivery::Lagged(count)) => {
+                        debug!(count, "missed power events");
+                        continue;
+                    }
+                    Err(MqttError::Shutdown) => break,
+                    Err(e) => Err(e)?,
+                };
+                let Some((TopicPrefix::Stat, device, name)) = topics.parse(&msg.topic) else {
+                    continue;
+                };
+                for event in events.handle(device, name, msg.payload.as_ref()) {
+                    yield event;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_pulse_times() {
+        assert_eq!(encode_pulse_time(Duration::ZERO), 0);
+        assert_eq!(encode_pulse_time(Duration::from_millis(50)), 1);
+        assert_eq!(encode_pulse_time(Duration::from_millis(100)), 1);
+        assert_eq!(encode_pulse_time(Duration::from_millis(1450)), 15);
+        assert_eq!(encode_pulse_time(Duration::from_millis(1500)), 15);
+        assert_eq!(encode_pulse_time(Duration::from_millis(1501)), 16);
+        assert_eq!(encode_pulse_time(Duration::from_millis(11_100)), 111);
+        assert_eq!(encode_pulse_time(Duration::from_millis(11_101)), 112);
+        assert_eq!(encode_pulse_time(Duration::from_millis(11_990)), 112);
+        assert_eq!(encode_pulse_time(Duration::from_millis(12_001)), 113);
+        assert_eq!(encode_pulse_time(Duration::from_secs(12)), 112);
+        assert_eq!(encode_pulse_time(Duration::from_millis(12_500)), 113);
+        assert_eq!(encode_pulse_time(Duration::from_secs(3600)), 3700);
+    }
+
+    #[test]
+    fn decode_pulse_times() {
+        assert_eq!(decode_pulse_time(0), Duration::ZERO);
+        assert_eq!(decode_pulse_time(1), Duration::from_millis(100));
+        assert_eq!(decode_pulse_time(111), Duration::from_millis(11_100));
+        assert_eq!(decode_pulse_time(112), Duration::from_secs(12));
+        assert_eq!(decode_pulse_time(3700), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn pulse_time_command() {
+        assert_eq!(PulseTime::get(2).name(), "PulseTime2");
+        assert_eq!(PulseTime::get(2).payload(), "");
+        assert_eq!(
+            PulseTime::set(1, Duration::from_millis(11_200)).payload(),
+            "112"
+        );
+    }
+}
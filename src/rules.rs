@@ -0,0 +1,320 @@
+//! Management of the rule sets of a device
+
+use crate::commands::{Rule, RuleInfo};
+use crate::{Error, Result, TasmotaClient};
+use std::collections::BTreeMap;
+
+/// Number of rule sets of a device
+pub const RULE_SETS: u8 = 3;
+
+/// Maximum length of the rule text sent in a single command
+///
+/// Tasmota rejects rule commands of 512 bytes or longer, longer rules are appended in multiple commands.
+const CHUNK_SIZE: usize = 400;
+
+/// Trim the rule text and turn line breaks and tabs into spaces, the way the rule text is compared
+///
+/// The number of spaces is kept, since it matters for payloads like the text of a `Publish`.
+fn normalize(text: &str) -> String {
+    text.trim().replace(char::is_whitespace, " ")
+}
+
+/// Split a rule text into commands that each fit in a single command
+///
+/// All but the first chunk are prefixed by `+`, which makes tasmota append them to the existing rule.
+/// Chunks are only split at whitespace, which is replaced by the space tasmota inserts when appending.
+fn rule_chunks(text: &str) -> Vec<String> {
+    let mut rest = text.trim();
+    if rest.is_empty() {
+        // a single quote clears the rule set
+        return vec!["\"".into()];
+    }
+    let mut chunks = Vec::new();
+    while rest.len() > CHUNK_SIZE {
+        let mut separators = rest
+            .char_indices()
+            .filter(|(i, c)| *i > 0 && c.is_whitespace());
+        // the last separator that keeps the chunk within the limit, or the first one if a word is longer than that
+        let Some((cut, separator)) = separators
+            .clone()
+            .take_while(|(i, _)| *i <= CHUNK_SIZE)
+            .last()
+            .or_else(|| separators.next())
+        else {
+            break;
+        };
+        chunks.push(rest[..cut].to_string());
+        rest = &rest[cut + separator.len_utf8()..];
+    }
+    chunks.push(rest.to_string());
+    for chunk in chunks.iter_mut().skip(1) {
+        chunk.insert(0, '+');
+    }
+    chunks
+}
+
+/// Split a rule set into the individual `ON ... ENDON` rules
+pub fn split_rules(text: &str) -> Vec<String> {
+    let mut rules = Vec::new();
+    let mut rule_start = 0;
+    let mut word_start = None;
+    // a trailing separator to end the last word
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (c.is_whitespace(), word_start) {
+            (false, None) => word_start = Some(i),
+            (true, Some(start)) => {
+                word_start = None;
+                if text[start..i].eq_ignore_ascii_case("endon") {
+                    rules.push(normalize(&text[rule_start..i]));
+                    rule_start = i;
+                }
+            }
+            _ => {}
+        }
+    }
+    let rest = normalize(&text[rule_start..]);
+    if !rest.is_empty() {
+        rules.push(rest);
+    }
+    rules
+}
+
+/// Difference between the rules stored on a device and the desired rules
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleDiff {
+    /// Rules that are stored on the device but not desired
+    pub removed: Vec<String>,
+    /// Desired rules that are missing on the device
+    pub added: Vec<String>,
+    /// Both contain the same rules, but in a different order
+    pub reordered: bool,
+}
+
+impl RuleDiff {
+    /// Compare the rule text stored on a device with the desired text, ignoring the whitespace between rules
+    pub fn new(current: &str, desired: &str) -> Self {
+        let current = split_rules(current);
+        let desired = split_rules(desired);
+
+        let mut removed = current.clone();
+        let mut added = Vec::new();
+        for rule in &desired {
+            match removed.iter().position(|existing| existing == rule) {
+                Some(position) => {
+                    removed.remove(position);
+                }
+                None => added.push(rule.clone()),
+            }
+        }
+        let reordered = removed.is_empty() && added.is_empty() && current != desired;
+        RuleDiff {
+            removed,
+            added,
+            reordered,
+        }
+    }
+
+    /// Whether the stored rules match the desired rules
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty() && !self.reordered
+    }
+}
+
+/// A flag of a rule set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleOption {
+    /// Whether the rule set is active
+    Enabled(bool),
+    /// Whether every rule only triggers once, until its trigger condition is no longer met
+    Once(bool),
+    /// Whether the rule set is disabled when a command in it fails
+    StopOnError(bool),
+}
+
+impl RuleOption {
+    /// The payload for the rule command that sets the option
+    fn value(&self) -> u8 {
+        match *self {
+            RuleOption::Enabled(enabled) => enabled as u8,
+            RuleOption::Once(once) => 4 + once as u8,
+            RuleOption::StopOnError(stop) => 8 + stop as u8,
+        }
+    }
+}
+
+impl TasmotaClient {
+    /// Get the text and flags of all rule sets, by rule set index starting at 1
+    #[tracing::instrument(skip(self))]
+    pub async fn get_rules(&self, device: &str) -> Result<BTreeMap<u8, RuleInfo>> {
+        let mut rules = BTreeMap::new();
+        for index in 1..=RULE_SETS {
+            let info = self
+                .execute(
+                    device,
+                    &Rule {
+                        index,
+                        payload: None,
+                    },
+                )
+                .await?
+                .0;
+            rules.insert(index, info);
+        }
+        Ok(rules)
+    }
+
+    /// Replace the text of a rule set, rule sets are numbered from 1
+    ///
+    /// Long rules are sent in multiple commands. The flags of the rule set are not changed,
+    /// use [`set_rule_option`](Self::set_rule_option) to enable the rule set.
+    /// Returns an error if the rule text stored on the device doesn't match the text that was sent,
+    /// which happens when the rule set is full.
+    #[tracing::instrument(skip(self, text))]
+    pub async fn set_rule(&self, device: &str, index: u8, text: &str) -> Result<RuleInfo> {
+        let mut info = None;
+        for chunk in rule_chunks(text) {
+            let reply = self
+                .execute(
+                    device,
+                    &Rule {
+                        index,
+                        payload: Some(chunk),
+                    },
+                )
+                .await?;
+            info = Some(reply.0);
+        }
+        let info = info.ok_or(Error::RuleMismatch(index))?;
+        if normalize(&info.rules) != normalize(text) {
+            return Err(Error::RuleMismatch(index));
+        }
+        Ok(info)
+    }
+
+    /// Change a flag of a rule set, rule sets are numbered from 1
+    #[tracing::instrument(skip(self))]
+    pub async fn set_rule_option(
+        &self,
+        device: &str,
+        index: u8,
+        option: RuleOption,
+    ) -> Result<RuleInfo> {
+        Ok(self
+            .execute(
+                device,
+                &Rule {
+                    index,
+                    payload: Some(option.value().to_string()),
+                },
+            )
+            .await?
+            .0)
+    }
+
+    /// Compare a rule set stored on a device with the desired rule text
+    #[tracing::instrument(skip(self, desired))]
+    pub async fn diff_rule(&self, device: &str, index: u8, desired: &str) -> Result<RuleDiff> {
+        let current = self
+            .execute(
+                device,
+                &Rule {
+                    index,
+                    payload: None,
+                },
+            )
+            .await?
+            .0;
+        Ok(RuleDiff::new(&current.rules, desired))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_rule_is_single_chunk() {
+        assert_eq!(
+            rule_chunks("  on Power1#State do Publish stat/x  a  b endon\n"),
+            vec!["on Power1#State do Publish stat/x  a  b endon"]
+        );
+        assert_eq!(rule_chunks(" \n"), vec!["\""]);
+    }
+
+    #[test]
+    fn chunks_split_at_whitespace() {
+        // 80 words of 4 bytes take 399 bytes, one more word crosses the limit
+        let words = vec!["abcd"; 150];
+        let text = words.join(" ");
+        let chunks = rule_chunks(&text);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0], words[..80].join(" "));
+        assert_eq!(chunks[0].len(), 399);
+        assert_eq!(chunks[1], format!("+{}", words[80..].join(" ")));
+
+        // a chunk may fill the limit exactly
+        let text = format!("{} tail", "x".repeat(CHUNK_SIZE));
+        assert_eq!(
+            rule_chunks(&text),
+            vec!["x".repeat(CHUNK_SIZE), "+tail".into()]
+        );
+        let text = format!("{} tail", "x".repeat(CHUNK_SIZE + 1));
+        assert_eq!(
+            rule_chunks(&text),
+            vec!["x".repeat(CHUNK_SIZE + 1), "+tail".into()]
+        );
+    }
+
+    #[test]
+    fn chunks_keep_spacing() {
+        let text = format!("{} Publish a  \"b   c\"", "x".repeat(CHUNK_SIZE - 1));
+        let chunks = rule_chunks(&text);
+        assert_eq!(chunks[1], "+Publish a  \"b   c\"");
+        // tasmota joins the chunks with a single space
+        let joined = format!("{} {}", chunks[0], &chunks[1][1..]);
+        assert_eq!(joined, text);
+    }
+
+    #[test]
+    fn split_rules_at_endon() {
+        let text = "on a do b endon\n  ON c do Publish x  \"y  z\" ENDON trailing";
+        assert_eq!(
+            split_rules(text),
+            vec![
+                "on a do b endon",
+                "ON c do Publish x  \"y  z\" ENDON",
+                "trailing"
+            ]
+        );
+        assert!(split_rules("  ").is_empty());
+    }
+
+    #[test]
+    fn rule_diff() {
+        let diff = RuleDiff::new(
+            "on a do b endon on c do d endon",
+            "on a do b endon\non c do d endon",
+        );
+        assert!(diff.is_empty());
+
+        let diff = RuleDiff::new(
+            "on a do b endon on c do d endon",
+            "on c do d endon on a do b endon",
+        );
+        assert!(diff.reordered);
+        assert!(diff.removed.is_empty() && diff.added.is_empty());
+
+        let diff = RuleDiff::new(
+            "on a do b endon on c do d endon",
+            "on a do b endon on e do f endon",
+        );
+        assert!(!diff.reordered);
+        assert_eq!(diff.removed, vec!["on c do d endon"]);
+        assert_eq!(diff.added, vec!["on e do f endon"]);
+
+        // spacing inside a rule is significant
+        let diff = RuleDiff::new("on a do Publish x  y endon", "on a do Publish x y endon");
+        assert_eq!(diff.removed, vec!["on a do Publish x  y endon"]);
+        assert_eq!(diff.added, vec!["on a do Publish x y endon"]);
+    }
+}
@@ -0,0 +1,660 @@
+use crate::error::SettingsError;
+use crate::DownloadedFile;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Size of the settings struct stored on the device
+pub(crate) const SETTINGS_SIZE: usize = 4096;
+/// Oldest firmware version with a settings layout we understand
+const MIN_SUPPORTED_VERSION: u32 = 0x09010000;
+/// Key used to scramble the settings when exporting them
+const CONFIG_FILE_XOR: u8 = 0x5A;
+/// The first bytes of the settings aren't scrambled
+const XOR_START: usize = 2;
+
+const VERSION: usize = 0x008;
+const BOOT_COUNT: usize = 0x00C;
+const CFG_CRC: usize = 0x00E;
+/// Number of bytes covered by the 16 bit checksum, which predates the settings growing to 4096 bytes
+const CFG_CRC_SIZE: usize = 3584;
+const FLAG: usize = 0x010;
+const TIMEZONE: usize = 0x016;
+const TEXT_POOL: usize = 0x017;
+const TEXT_POOL_SIZE: usize = 699;
+const FLAG4: usize = 0x1E0;
+const TELE_PERIOD: usize = 0x2F8;
+const PARAM: usize = 0x2FC;
+const PARAM_SIZE: usize = 18;
+const FLAG3: usize = 0x3A0;
+const MODULE: usize = 0x474;
+const TIMERS: usize = 0x670;
+const TIMER_COUNT: usize = 16;
+const TEMPLATE_BASE: usize = 0x71F;
+const TEMPLATE: usize = 0x720;
+const TEMPLATE_GPIO_COUNT: usize = 14;
+const RULES: usize = 0x800;
+const RULE_SIZE: usize = 512;
+const RULE_COUNT: usize = 3;
+/// The chip the settings are from, 0 for ESP8266 and the ESP32 variants above that
+const CONFIG_VERSION: usize = 0xF36;
+const CONFIG_VERSION_ESP8266: u8 = 0;
+const CFG_CRC32: usize = 0xFFC;
+
+/// Index of the strings stored in the settings text pool
+#[derive(Debug, Clone, Copy)]
+enum TextIndex {
+    MqttPrefix1 = 1,
+    StaSsid1 = 4,
+    Hostname = 8,
+    MqttHost = 12,
+    MqttClient = 13,
+    MqttUser = 14,
+    MqttFullTopic = 16,
+    MqttTopic = 17,
+    MqttGroupTopic = 20,
+    FriendlyName1 = 44,
+    TemplateName = 71,
+    DeviceName = 76,
+}
+
+const MQTT_PREFIX_COUNT: usize = 3;
+const SSID_COUNT: usize = 2;
+const FRIENDLY_NAME_COUNT: usize = 8;
+
+/// Firmware version the settings were saved with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SettingsVersion(pub u32);
+
+impl Display for SettingsVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let [major, minor, patch, build] = self.0.to_be_bytes();
+        write!(f, "{major}.{minor}.{patch}.{build}")
+    }
+}
+
+impl FromStr for SettingsVersion {
+    type Err = SettingsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0; 4];
+        let mut parts = s.split('.');
+        for byte in bytes.iter_mut() {
+            *byte = match parts.next() {
+                Some(part) => part
+                    .parse()
+                    .map_err(|_| SettingsError::InvalidVersion(s.into()))?,
+                None => 0,
+            };
+        }
+        if parts.next().is_some() {
+            return Err(SettingsError::InvalidVersion(s.into()));
+        }
+        Ok(SettingsVersion(u32::from_be_bytes(bytes)))
+    }
+}
+
+impl Serialize for SettingsVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for SettingsVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        raw.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Mqtt related settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MqttSettings {
+    pub host: String,
+    pub client: String,
+    pub user: String,
+    pub topic: String,
+    pub full_topic: String,
+    pub group_topic: String,
+    /// The command, stat and tele prefixes
+    pub prefixes: Vec<String>,
+}
+
+/// The user template stored on an ESP8266 device
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TemplateSettings {
+    pub name: String,
+    pub gpio: Vec<u16>,
+    pub flag: u16,
+    pub base: u8,
+}
+
+/// A timer as stored in the settings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimerSettings {
+    /// Minutes since midnight
+    pub time: u16,
+    /// Random window in minutes
+    pub window: u8,
+    pub repeat: bool,
+    /// Bitmask of the days the timer is active on, starting with sunday in the lowest bit
+    pub days: u8,
+    /// Output triggered by the timer, zero based
+    pub output: u8,
+    pub action: u8,
+    pub mode: u8,
+    pub arm: bool,
+}
+
+impl From<u32> for TimerSettings {
+    fn from(data: u32) -> Self {
+        TimerSettings {
+            time: (data & 0x7FF) as u16,
+            window: ((data >> 11) & 0xF) as u8,
+            repeat: (data >> 15) & 1 == 1,
+            days: ((data >> 16) & 0x7F) as u8,
+            output: ((data >> 23) & 0xF) as u8,
+            action: ((data >> 27) & 0x3) as u8,
+            mode: ((data >> 29) & 0x3) as u8,
+            arm: (data >> 31) & 1 == 1,
+        }
+    }
+}
+
+impl From<TimerSettings> for u32 {
+    fn from(timer: TimerSettings) -> Self {
+        (timer.time as u32 & 0x7FF)
+            | (timer.window as u32 & 0xF) << 11
+            | (timer.repeat as u32) << 15
+            | (timer.days as u32 & 0x7F) << 16
+            | (timer.output as u32 & 0xF) << 23
+            | (timer.action as u32 & 0x3) << 27
+            | (timer.mode as u32 & 0x3) << 29
+            | (timer.arm as u32) << 31
+    }
+}
+
+/// Values of `SetOption0` through `SetOption113`
+///
+/// Options that are flags have a value of 0 or 1.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SetOptions(BTreeMap<u8, u8>);
+
+impl SetOptions {
+    /// Number of options stored in the settings
+    pub const COUNT: u8 = 114;
+
+    /// Get the value for a `SetOption`
+    pub fn get(&self, option: u8) -> Option<u8> {
+        self.0.get(&option).copied()
+    }
+
+    /// Change the value for a `SetOption`
+    pub fn set(&mut self, option: u8, value: u8) {
+        if option < Self::COUNT {
+            self.0.insert(option, value);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        self.0.iter().map(|(option, value)| (*option, *value))
+    }
+}
+
+/// The decoded settings of a device
+///
+/// Obtained by decoding a config backup from [`TasmotaClient::download_config`](crate::TasmotaClient::download_config).
+/// Passwords aren't included.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TasmotaSettings {
+    pub version: SettingsVersion,
+    pub boot_count: u16,
+    pub hostname: String,
+    pub device_name: String,
+    pub friendly_names: Vec<String>,
+    pub timezone: i8,
+    pub tele_period: u16,
+    pub mqtt: MqttSettings,
+    pub wifi_ssids: Vec<String>,
+    pub set_options: SetOptions,
+    pub module: u8,
+    /// The user template, `None` for ESP32 devices since their template layout isn't supported
+    pub template: Option<TemplateSettings>,
+    /// Rule sets, `None` if the rule set is stored compressed
+    pub rules: Vec<Option<String>>,
+    pub timers: Vec<TimerSettings>,
+}
+
+impl TasmotaSettings {
+    /// Decode a `.dmp` config backup
+    pub fn decode(data: &[u8]) -> Result<Self, SettingsError> {
+        let settings = unscramble(data)?;
+        let settings = settings.as_slice();
+        validate(settings)?;
+        let version = read_u32(settings, VERSION);
+
+        let texts = text_pool(settings);
+        let text = |index: TextIndex| texts.get(index as usize).cloned().unwrap_or_default();
+        let texts_from = |index: TextIndex, count: usize| {
+            texts
+                .iter()
+                .skip(index as usize)
+                .take(count)
+                .cloned()
+                .collect()
+        };
+
+        Ok(TasmotaSettings {
+            version: SettingsVersion(version),
+            boot_count: read_u16(settings, BOOT_COUNT),
+            hostname: text(TextIndex::Hostname),
+            device_name: text(TextIndex::DeviceName),
+            friendly_names: texts_from(TextIndex::FriendlyName1, FRIENDLY_NAME_COUNT),
+            timezone: settings[TIMEZONE] as i8,
+            tele_period: read_u16(settings, TELE_PERIOD),
+            mqtt: MqttSettings {
+                host: text(TextIndex::MqttHost),
+                client: text(TextIndex::MqttClient),
+                user: text(TextIndex::MqttUser),
+                topic: text(TextIndex::MqttTopic),
+                full_topic: text(TextIndex::MqttFullTopic),
+                group_topic: text(TextIndex::MqttGroupTopic),
+                prefixes: texts_from(TextIndex::MqttPrefix1, MQTT_PREFIX_COUNT),
+            },
+            wifi_ssids: texts_from(TextIndex::StaSsid1, SSID_COUNT),
+            set_options: read_set_options(settings),
+            module: settings[MODULE],
+            template: (settings[CONFIG_VERSION] == CONFIG_VERSION_ESP8266).then(|| {
+                TemplateSettings {
+                    name: text(TextIndex::TemplateName),
+                    gpio: (0..TEMPLATE_GPIO_COUNT)
+                        .map(|pin| read_u16(settings, TEMPLATE + pin * 2))
+                        .collect(),
+                    flag: read_u16(settings, TEMPLATE + TEMPLATE_GPIO_COUNT * 2),
+                    base: settings[TEMPLATE_BASE],
+                }
+            }),
+            rules: (0..RULE_COUNT)
+                .map(|rule| read_rule(&settings[RULES + rule * RULE_SIZE..][..RULE_SIZE]))
+                .collect(),
+            timers: (0..TIMER_COUNT)
+                .map(|timer| read_u32(settings, TIMERS + timer * 4).into())
+                .collect(),
+        })
+    }
+
+    /// Encode the settings into a `.dmp` config backup that can be restored with [`TasmotaClient::upload_config`](crate::TasmotaClient::upload_config)
+    ///
+    /// Not every setting is part of [`TasmotaSettings`], so the settings are applied on top of an existing backup.
+    /// Anything not covered, including passwords and the firmware version, is kept from the base backup.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let backup = client.download_config("tasmota_device", "tasmota_device_mqtt_password").await?;
+    /// let mut settings = backup.settings()?;
+    /// settings.hostname = "new_device".into();
+    /// settings.mqtt.topic = "new_device".into();
+    /// let cloned = settings.encode(&backup)?;
+    /// client.upload_config("new_device", "new_device_mqtt_password", &cloned).await?;
+    ///     # Ok(())
+    /// # }
+    /// ```
+    pub fn encode(&self, base: &DownloadedFile) -> Result<DownloadedFile, SettingsError> {
+        let mut settings = unscramble(&base.data)?;
+        validate(&settings)?;
+        if self.template.is_some() && settings[CONFIG_VERSION] != CONFIG_VERSION_ESP8266 {
+            return Err(SettingsError::UnsupportedTemplate);
+        }
+
+        let mut texts = text_pool(&settings);
+        let mut set_text = |index: TextIndex, values: &[&str]| {
+            let index = index as usize;
+            if texts.len() < index + values.len() {
+                texts.resize(index + values.len(), String::new());
+            }
+            for (i, value) in values.iter().enumerate() {
+                texts[index + i] = value.to_string();
+            }
+        };
+        set_text(TextIndex::Hostname, &[&self.hostname]);
+        set_text(TextIndex::DeviceName, &[&self.device_name]);
+        set_text(TextIndex::MqttHost, &[&self.mqtt.host]);
+        set_text(TextIndex::MqttClient, &[&self.mqtt.client]);
+        set_text(TextIndex::MqttUser, &[&self.mqtt.user]);
+        set_text(TextIndex::MqttTopic, &[&self.mqtt.topic]);
+        set_text(TextIndex::MqttFullTopic, &[&self.mqtt.full_topic]);
+        set_text(TextIndex::MqttGroupTopic, &[&self.mqtt.group_topic]);
+        set_text(
+            TextIndex::FriendlyName1,
+            &padded(&self.friendly_names, FRIENDLY_NAME_COUNT),
+        );
+        set_text(
+            TextIndex::MqttPrefix1,
+            &padded(&self.mqtt.prefixes, MQTT_PREFIX_COUNT),
+        );
+        set_text(TextIndex::StaSsid1, &padded(&self.wifi_ssids, SSID_COUNT));
+        if let Some(template) = &self.template {
+            set_text(TextIndex::TemplateName, &[&template.name]);
+        }
+        write_text_pool(&mut settings, &texts)?;
+
+        settings[TIMEZONE] = self.timezone as u8;
+        write_u16(&mut settings, TELE_PERIOD, self.tele_period);
+        write_set_options(&mut settings, &self.set_options);
+        settings[MODULE] = self.module;
+        if let Some(template) = &self.template {
+            for (pin, gpio) in template.gpio.iter().take(TEMPLATE_GPIO_COUNT).enumerate() {
+                write_u16(&mut settings, TEMPLATE + pin * 2, *gpio);
+            }
+            write_u16(
+                &mut settings,
+                TEMPLATE + TEMPLATE_GPIO_COUNT * 2,
+                template.flag,
+            );
+            settings[TEMPLATE_BASE] = template.base;
+        }
+        for (i, rule) in self.rules.iter().take(RULE_COUNT).enumerate() {
+            if let Some(rule) = rule {
+                write_rule(&mut settings[RULES + i * RULE_SIZE..][..RULE_SIZE], i, rule)?;
+            }
+        }
+        for (i, timer) in self.timers.iter().take(TIMER_COUNT).enumerate() {
+            write_u32(&mut settings, TIMERS + i * 4, (*timer).into());
+        }
+
+        let crc = settings_crc16(&settings);
+        write_u16(&mut settings, CFG_CRC, crc);
+        let crc = settings_crc32(&settings);
+        write_u32(&mut settings, CFG_CRC32, crc);
+
+        scramble(&mut settings);
+        Ok(DownloadedFile::new(base.name.clone(), settings))
+    }
+}
+
+/// Check that the settings are from a supported version and haven't been corrupted
+fn validate(settings: &[u8]) -> Result<(), SettingsError> {
+    let version = read_u32(settings, VERSION);
+    if version < MIN_SUPPORTED_VERSION {
+        return Err(SettingsError::UnsupportedVersion(SettingsVersion(version)));
+    }
+
+    // the crc32 covers the 16 bit checksum as well, which is only kept for backwards compatibility
+    let expected = read_u32(settings, CFG_CRC32);
+    let actual = settings_crc32(settings);
+    if expected != actual {
+        return Err(SettingsError::MismatchedChecksum(expected, actual));
+    }
+    Ok(())
+}
+
+/// Undo the xor scrambling applied to exported settings
+///
+/// Only plain settings dumps are supported, larger ESP32 dumps start with a header and are scrambled differently.
+pub(crate) fn unscramble(data: &[u8]) -> Result<Vec<u8>, SettingsError> {
+    if data.len() != SETTINGS_SIZE {
+        return Err(SettingsError::InvalidSize(data.len()));
+    }
+    let mut settings = data.to_vec();
+    scramble(&mut settings);
+    Ok(settings)
+}
+
+/// Apply the xor scrambling used for exported settings, the scrambling is its own inverse
+pub(crate) fn scramble(settings: &mut [u8]) {
+    for (i, byte) in settings.iter_mut().enumerate().skip(XOR_START) {
+        *byte ^= CONFIG_FILE_XOR.wrapping_add(i as u8);
+    }
+}
+
+pub(crate) fn read_u16(settings: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([settings[offset], settings[offset + 1]])
+}
+
+pub(crate) fn read_u32(settings: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&settings[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+pub(crate) fn write_u16(settings: &mut [u8], offset: usize, value: u16) {
+    settings[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u32(settings: &mut [u8], offset: usize, value: u32) {
+    settings[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The 16 bit checksum stored at `cfg_crc`
+pub(crate) fn settings_crc16(settings: &[u8]) -> u16 {
+    settings[..CFG_CRC_SIZE]
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != CFG_CRC && *i != CFG_CRC + 1)
+        .fold(0u16, |crc, (i, byte)| {
+            crc.wrapping_add((*byte as u16).wrapping_mul(i as u16 + 1))
+        })
+}
+
+/// The crc32 stored at the end of the settings
+pub(crate) fn settings_crc32(settings: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for byte in &settings[..CFG_CRC32] {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ ((crc & 1).wrapping_neg() & 0xEDB88320);
+        }
+    }
+    !crc
+}
+
+fn text_pool(settings: &[u8]) -> Vec<String> {
+    settings[TEXT_POOL..TEXT_POOL + TEXT_POOL_SIZE]
+        .split(|byte| *byte == 0)
+        .map(|text| String::from_utf8_lossy(text).into_owned())
+        .collect()
+}
+
+/// Take `count` texts, filling up with empty texts if needed
+fn padded(texts: &[String], count: usize) -> Vec<&str> {
+    (0..count)
+        .map(|i| texts.get(i).map(String::as_str).unwrap_or_default())
+        .collect()
+}
+
+fn write_text_pool(settings: &mut [u8], texts: &[String]) -> Result<(), SettingsError> {
+    // trailing empty texts are the same as missing texts, drop them to make room for longer values
+    let used = texts
+        .iter()
+        .rposition(|text| !text.is_empty())
+        .map_or(0, |last| last + 1);
+    let pool = texts[..used].join("\0");
+    // the last text needs to be nul terminated
+    if pool.len() >= TEXT_POOL_SIZE {
+        return Err(SettingsError::TextPoolFull(pool.len() + 1, TEXT_POOL_SIZE));
+    }
+    let target = &mut settings[TEXT_POOL..TEXT_POOL + TEXT_POOL_SIZE];
+    target.fill(0);
+    target[..pool.len()].copy_from_slice(pool.as_bytes());
+    Ok(())
+}
+
+fn read_set_options(settings: &[u8]) -> SetOptions {
+    let mut options = SetOptions::default();
+    let flag_banks = [(0, FLAG), (50, FLAG3), (82, FLAG4)];
+    for (first, offset) in flag_banks {
+        let flags = read_u32(settings, offset);
+        for bit in 0..32 {
+            options.set(first + bit, ((flags >> bit) & 1) as u8);
+        }
+    }
+    for (i, value) in settings[PARAM..PARAM + PARAM_SIZE].iter().enumerate() {
+        options.set(32 + i as u8, *value);
+    }
+    options
+}
+
+fn write_set_options(settings: &mut [u8], options: &SetOptions) {
+    let flag_banks = [(0, FLAG), (50, FLAG3), (82, FLAG4)];
+    for (first, offset) in flag_banks {
+        let mut flags = read_u32(settings, offset);
+        for bit in 0..32 {
+            match options.get(first + bit) {
+                Some(0) => flags &= !(1 << bit),
+                Some(_) => flags |= 1 << bit,
+                None => {}
+            }
+        }
+        write_u32(settings, offset, flags);
+    }
+    for i in 0..PARAM_SIZE {
+        if let Some(value) = options.get(32 + i as u8) {
+            settings[PARAM + i] = value;
+        }
+    }
+}
+
+fn read_rule(rule: &[u8]) -> Option<String> {
+    // compressed rules start with a nul byte followed by the compressed data
+    if rule[0] == 0 && rule[1] != 0 {
+        return None;
+    }
+    let end = rule
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(rule.len());
+    Some(String::from_utf8_lossy(&rule[..end]).into_owned())
+}
+
+fn write_rule(target: &mut [u8], index: usize, rule: &str) -> Result<(), SettingsError> {
+    // the rule needs to be nul terminated
+    if rule.len() >= target.len() {
+        return Err(SettingsError::RuleTooLong(index + 1, rule.len()));
+    }
+    target.fill(0);
+    target[..rule.len()].copy_from_slice(rule.as_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A synthetic ESP8266 dump in the 13.1.0 layout
+    ///
+    /// It was generated from random bytes by writing the values asserted in `decode_dump` at their offsets,
+    /// clearing the rest of the text pool and rules, computing both checksums and scrambling the result.
+    /// Everything else, including most of the area after the rules, is random filler.
+    const DUMP: &[u8] = include_bytes!("../tests/data/synthetic_esp8266_13.1.0.dmp");
+
+    #[test]
+    fn decode_dump() {
+        let settings = TasmotaSettings::decode(DUMP).unwrap();
+        assert_eq!(settings.version.to_string(), "13.1.0.0");
+        assert_eq!(settings.boot_count, 17);
+        assert_eq!(settings.hostname, "tasmota-AB12CD-3456");
+        assert_eq!(settings.device_name, "Kitchen");
+        assert_eq!(settings.mqtt.topic, "kitchen_plug");
+        assert_eq!(settings.mqtt.full_topic, "%prefix%/%topic%/");
+        assert_eq!(settings.mqtt.prefixes, ["cmnd", "stat", "tele"]);
+        assert_eq!(settings.timezone, 99);
+        assert_eq!(settings.tele_period, 300);
+        let template = settings.template.unwrap();
+        assert_eq!(template.name, "Sonoff Basic");
+        assert_eq!(
+            template.gpio,
+            [32, 1, 1, 1, 1, 0, 0, 0, 224, 320, 1, 0, 0, 0]
+        );
+        assert_eq!(template.base, 1);
+        assert_eq!(settings.timers[0].time, 7 * 60 + 30);
+        assert_eq!(settings.timers[0].days, 0b0111110);
+        assert!(settings.timers[0].arm);
+        assert_eq!(
+            settings.rules[0].as_deref(),
+            Some(r#"ON Power1#State=1 DO Publish stat/kitchen_plug/on  {"state": "on"} ENDON"#)
+        );
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let base = DownloadedFile::new("Config_kitchen_plug.dmp", DUMP);
+        let settings = TasmotaSettings::decode(DUMP).unwrap();
+        let encoded = settings.encode(&base).unwrap();
+        assert_eq!(encoded.data.as_ref(), DUMP);
+
+        let raw = unscramble(&encoded.data).unwrap();
+        assert_eq!(read_u16(&raw, CFG_CRC), settings_crc16(&raw));
+        assert_eq!(read_u32(&raw, CFG_CRC32), settings_crc32(&raw));
+    }
+
+    #[test]
+    fn encode_updates_checksums() {
+        let base = DownloadedFile::new("Config_kitchen_plug.dmp", DUMP);
+        let mut settings = TasmotaSettings::decode(DUMP).unwrap();
+        settings.hostname = "living-room".into();
+        settings.mqtt.topic = "living_room_plug".into();
+        let encoded = settings.encode(&base).unwrap();
+
+        let raw = unscramble(&encoded.data).unwrap();
+        assert_eq!(read_u16(&raw, CFG_CRC), settings_crc16(&raw));
+        assert_eq!(read_u32(&raw, CFG_CRC32), settings_crc32(&raw));
+        assert_eq!(TasmotaSettings::decode(&encoded.data).unwrap(), settings);
+    }
+
+    #[test]
+    fn reject_corrupted_dump() {
+        let mut data = DUMP.to_vec();
+        data[0x100] ^= 0xFF;
+        assert!(matches!(
+            TasmotaSettings::decode(&data),
+            Err(SettingsError::MismatchedChecksum(..))
+        ));
+    }
+
+    #[test]
+    fn reject_invalid_size() {
+        let mut data = DUMP.to_vec();
+        data.extend_from_slice(&[0; 16]);
+        assert!(matches!(
+            TasmotaSettings::decode(&data),
+            Err(SettingsError::InvalidSize(4112))
+        ));
+        assert!(matches!(
+            TasmotaSettings::decode(&DUMP[..4000]),
+            Err(SettingsError::InvalidSize(4000))
+        ));
+    }
+
+    #[test]
+    fn skip_esp32_template() {
+        let mut raw = unscramble(DUMP).unwrap();
+        raw[CONFIG_VERSION] = 1;
+        let crc = settings_crc32(&raw);
+        write_u32(&mut raw, CFG_CRC32, crc);
+        scramble(&mut raw);
+        let base = DownloadedFile::new("Config_esp32.dmp", raw);
+
+        let mut settings = base.settings().unwrap();
+        assert_eq!(settings.template, None);
+        assert_eq!(settings.hostname, "tasmota-AB12CD-3456");
+        assert!(settings.encode(&base).is_ok());
+
+        settings.template = TasmotaSettings::decode(DUMP).unwrap().template;
+        assert!(matches!(
+            settings.encode(&base),
+            Err(SettingsError::UnsupportedTemplate)
+        ));
+    }
+}
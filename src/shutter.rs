@@ -0,0 +1,383 @@
+//! Control of shutters and blinds in tasmota shutter mode
+
+use crate::commands::{single_value, Status, TasmotaCommand};
+use crate::error::MqttError;
+use crate::mqtt::{Delivery, OverflowPolicy};
+use crate::{Error, Result, TasmotaClient, TopicPrefix};
+use async_stream::try_stream;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::time::Duration;
+use tokio::select;
+use tokio_stream::Stream;
+use tracing::debug;
+
+/// Direction a shutter is moving in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutterDirection {
+    Closing,
+    Stopped,
+    Opening,
+}
+
+impl<'de> Deserialize<'de> for ShutterDirection {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match i8::deserialize(deserializer)? {
+            -1 => Ok(ShutterDirection::Closing),
+            0 => Ok(ShutterDirection::Stopped),
+            1 => Ok(ShutterDirection::Opening),
+            direction => Err(D::Error::custom(format!(
+                "invalid shutter direction {direction}"
+            ))),
+        }
+    }
+}
+
+/// Position and movement of a shutter, as reported in the `Shutter<index>` objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ShutterState {
+    /// Position in percent, 0 is closed and 100 is fully open
+    pub position: u8,
+    pub direction: ShutterDirection,
+    /// Position the shutter is moving to, in percent
+    pub target: u8,
+    /// Tilt of the slats in degrees
+    #[serde(default)]
+    pub tilt: i16,
+}
+
+/// The position or movement of a shutter was reported
+///
+/// See also [`TasmotaClient::shutter_updates`](crate::TasmotaClient::shutter_updates).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutterUpdate {
+    /// Topic of the device
+    pub device: String,
+    /// Index of the shutter, starting at 1
+    pub index: u8,
+    pub state: ShutterState,
+}
+
+/// Get the state of all shutters from a message containing `Shutter<index>` objects
+fn shutter_states(fields: &Map<String, Value>) -> Vec<(u8, ShutterState)> {
+    fields
+        .iter()
+        .filter_map(|(key, value)| {
+            let index = key.strip_prefix("Shutter")?.parse().ok()?;
+            match ShutterState::deserialize(value) {
+                Ok(state) => Some((index, state)),
+                Err(e) => {
+                    debug!(key, error = ?e, "failed to parse shutter state");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Move a shutter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutterAction {
+    Open,
+    Close,
+    Stop,
+    /// Move to a position in percent, 0 is closed and 100 is fully open
+    Position(u8),
+    /// Tilt the slats to an angle in degrees
+    Tilt(i16),
+}
+
+/// Move a shutter, shutters are numbered from 1
+#[derive(Debug, Clone)]
+pub struct ShutterMove {
+    pub index: u8,
+    pub action: ShutterAction,
+}
+
+/// Reply to shutter commands, the target position or other result of the command
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct ShutterResponse(#[serde(deserialize_with = "single_value")] pub Value);
+
+impl TasmotaCommand for ShutterMove {
+    type Response = ShutterResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        let command = match self.action {
+            ShutterAction::Open => "ShutterOpen",
+            ShutterAction::Close => "ShutterClose",
+            ShutterAction::Stop => "ShutterStop",
+            ShutterAction::Position(_) => "ShutterPosition",
+            ShutterAction::Tilt(_) => "ShutterTilt",
+        };
+        format!("{command}{}", self.index).into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        match self.action {
+            ShutterAction::Position(position) => position.to_string().into(),
+            ShutterAction::Tilt(tilt) => tilt.to_string().into(),
+            _ => "".into(),
+        }
+    }
+}
+
+/// Calibrate the movement of a shutter, shutters are numbered from 1
+#[derive(Debug, Clone)]
+pub struct ShutterCalibration {
+    pub index: u8,
+    pub setting: ShutterCalibrationSetting,
+}
+
+/// A calibration setting of a shutter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShutterCalibrationSetting {
+    /// Time it takes to fully open the shutter, in steps of 0.1 seconds
+    OpenDuration(Duration),
+    /// Time it takes to fully close the shutter, in steps of 0.1 seconds
+    CloseDuration(Duration),
+    /// Position in percent at which the shutter is halfway closed, usually where the slats start to close
+    Halfway(u8),
+}
+
+impl TasmotaCommand for ShutterCalibration {
+    type Response = ShutterResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        let command = match self.setting {
+            ShutterCalibrationSetting::OpenDuration(_) => "ShutterOpenDuration",
+            ShutterCalibrationSetting::CloseDuration(_) => "ShutterCloseDuration",
+            ShutterCalibrationSetting::Halfway(_) => "ShutterSetHalfway",
+        };
+        format!("{command}{}", self.index).into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        match self.setting {
+            ShutterCalibrationSetting::OpenDuration(duration)
+            | ShutterCalibrationSetting::CloseDuration(duration) => {
+                format!("{:.1}", duration.as_secs_f64()).into()
+            }
+            ShutterCalibrationSetting::Halfway(position) => position.to_string().into(),
+        }
+    }
+}
+
+impl TasmotaClient {
+    /// Move a shutter, shutters are numbered from 1
+    ///
+    /// The command returns once the device has started the movement,
+    /// use [`shutter_updates`](Self::shutter_updates) to follow the movement.
+    #[tracing::instrument(skip(self))]
+    pub async fn move_shutter(&self, device: &str, index: u8, action: ShutterAction) -> Result<()> {
+        self.execute(device, &ShutterMove { index, action }).await?;
+        Ok(())
+    }
+
+    /// Fully open a shutter
+    pub async fn open_shutter(&self, device: &str, index: u8) -> Result<()> {
+        self.move_shutter(device, index, ShutterAction::Open).await
+    }
+
+    /// Fully close a shutter
+    pub async fn close_shutter(&self, device: &str, index: u8) -> Result<()> {
+        self.move_shutter(device, index, ShutterAction::Close).await
+    }
+
+    /// Stop a moving shutter
+    pub async fn stop_shutter(&self, device: &str, index: u8) -> Result<()> {
+        self.move_shutter(device, index, ShutterAction::Stop).await
+    }
+
+    /// Get the position and movement of a shutter
+    #[tracing::instrument(skip(self))]
+    pub async fn shutter_position(&self, device: &str, index: u8) -> Result<ShutterState> {
+        let sensors = self.execute(device, &Status(10)).await?.0;
+        let fields = Map::deserialize(&sensors)?;
+        shutter_states(&fields)
+            .into_iter()
+            .find_map(|(shutter, state)| (shutter == index).then_some(state))
+            .ok_or_else(|| Error::MalformedReply("shutter position", sensors.to_string()))
+    }
+
+    /// Change a calibration setting of a shutter
+    #[tracing::instrument(skip(self))]
+    pub async fn calibrate_shutter(
+        &self,
+        device: &str,
+        index: u8,
+        setting: ShutterCalibrationSetting,
+    ) -> Result<()> {
+        self.execute(device, &ShutterCalibration { index, setting })
+            .await?;
+        Ok(())
+    }
+
+    /// Receive the position and movement of shutters whenever they are reported
+    ///
+    /// Updates are parsed from both the command replies and the periodic sensor telemetry.
+    /// The filter is either the topic of a single device, or `+` to receive the updates of all devices.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use std::pin::pin;
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// # use tokio_stream::StreamExt;
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let mut updates = pin!(client.shutter_updates("+"));
+    /// while let Some(update) = updates.next().await {
+    ///     let update = update?;
+    ///     println!("shutter {} of {} is at {}%", update.index, update.device, update.state.position);
+    /// }
+    ///     # Ok(())
+    /// # }
+    /// ```
+    pub fn shutter_updates<'a>(
+        &'a self,
+        device_filter: &'a str,
+    ) -> impl Stream<Item = Result<ShutterUpdate>> + 'a {
+        try_stream! {
+            let topics = self.topic_scheme(device_filter);
+            let mut sensors = self
+                .mqtt
+                .subscribe(
+                    topics.topic(TopicPrefix::Tele, device_filter, "SENSOR"),
+                    100,
+                    OverflowPolicy::DropOldest,
+                )
+                .await?;
+            let mut results = self
+                .mqtt
+                .subscribe(
+                    topics.topic(TopicPrefix::Stat, device_filter, "RESULT"),
+                    100,
+                    OverflowPolicy::DropOldest,
+                )
+                .await?;
+
+            loop {
+                let delivery = select! {
+                    delivery = sensors.next_delivery() => delivery,
+                    delivery = results.next_delivery() => delivery,
+                };
+                let msg = match delivery {
+                    Ok(Delivery::Message(msg)) => msg,
+                    Ok(Delivery::Lagged(count)) => {
+                        debug!(count, "missed shutter updates");
+                        continue;
+                    }
+                    Err(MqttError::Shutdown) => break,
+                    Err(e) => Err(e)?,
+                };
+                let Some((_, device, _)) = topics.parse(&msg.topic) else {
+                    continue;
+                };
+                let Ok(fields) = serde_json::from_slice::<Map<String, Value>>(msg.payload.as_ref()) else {
+                    continue;
+                };
+                for (index, state) in shutter_states(&fields) {
+                    yield ShutterUpdate {
+                        device: device.into(),
+                        index,
+                        state,
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn states(message: Value) -> Vec<(u8, ShutterState)> {
+        let Value::Object(fields) = message else {
+            unreachable!()
+        };
+        shutter_states(&fields)
+    }
+
+    #[test]
+    fn parse_shutter_states() {
+        let states = states(json!({
+            "Time": "2024-03-01T11:12:04",
+            "Shutter1": {"Position": 30, "Direction": -1, "Target": 0, "Tilt": 0},
+            "Shutter2": {"Position": 100, "Direction": 0, "Target": 100, "Tilt": -45},
+            "Shutter3": {"Position": 42, "Direction": 1, "Target": 80},
+        }));
+        assert_eq!(
+            states,
+            [
+                (
+                    1,
+                    ShutterState {
+                        position: 30,
+                        direction: ShutterDirection::Closing,
+                        target: 0,
+                        tilt: 0,
+                    }
+                ),
+                (
+                    2,
+                    ShutterState {
+                        position: 100,
+                        direction: ShutterDirection::Stopped,
+                        target: 100,
+                        tilt: -45,
+                    }
+                ),
+                (
+                    3,
+                    ShutterState {
+                        position: 42,
+                        direction: ShutterDirection::Opening,
+                        target: 80,
+                        tilt: 0,
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_invalid_shutter_states() {
+        let states = states(json!({
+            "Shutter1": {"Position": 30, "Direction": 2, "Target": 0},
+            "Shutter2": {"Position": 30, "Direction": "up", "Target": 0},
+            "ShutterTilt1": {"Position": 30, "Direction": 0, "Target": 0},
+            "Shutter3": {"Position": 30, "Direction": 0, "Target": 30},
+        }));
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].0, 3);
+    }
+
+    #[test]
+    fn shutter_commands() {
+        let position = ShutterMove {
+            index: 2,
+            action: ShutterAction::Position(40),
+        };
+        assert_eq!(position.name(), "ShutterPosition2");
+        assert_eq!(position.payload(), "40");
+
+        let stop = ShutterMove {
+            index: 1,
+            action: ShutterAction::Stop,
+        };
+        assert_eq!(stop.name(), "ShutterStop1");
+        assert_eq!(stop.payload(), "");
+    }
+}
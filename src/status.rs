@@ -0,0 +1,401 @@
+use crate::commands::PowerState;
+use crate::mqtt::OverflowPolicy;
+use crate::{Error, Result, TasmotaClient, TopicPrefix};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use tokio::time::timeout;
+use tracing::debug;
+
+/// Sections of the `Status 0` reply that make up a [`DeviceStatus`], by their top level key
+const STATUS_SECTIONS: &[&str] = &[
+    "Status",
+    "StatusPRM",
+    "StatusFWR",
+    "StatusMEM",
+    "StatusNET",
+    "StatusMQT",
+    "StatusTIM",
+    "StatusSNS",
+    "StatusSTS",
+];
+
+/// Full status of a device as reported by `Status 0`
+///
+/// Sections are `None` when the device didn't send them in time or when they couldn't be parsed.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStatus {
+    pub device: Option<StatusDevice>,
+    pub parameters: Option<StatusParameters>,
+    pub firmware: Option<StatusFirmware>,
+    pub memory: Option<StatusMemory>,
+    pub network: Option<StatusNetwork>,
+    pub mqtt: Option<StatusMqtt>,
+    pub time: Option<StatusTime>,
+    /// Sensor readings by sensor name
+    pub sensors: Option<Map<String, Value>>,
+    pub state: Option<StatusState>,
+}
+
+impl DeviceStatus {
+    fn from_sections(sections: &Map<String, Value>) -> Self {
+        DeviceStatus {
+            device: section(sections, "Status"),
+            parameters: section(sections, "StatusPRM"),
+            firmware: section(sections, "StatusFWR"),
+            memory: section(sections, "StatusMEM"),
+            network: section(sections, "StatusNET"),
+            mqtt: section(sections, "StatusMQT"),
+            time: section(sections, "StatusTIM"),
+            sensors: section(sections, "StatusSNS"),
+            state: section(sections, "StatusSTS"),
+        }
+    }
+}
+
+fn section<T: DeserializeOwned>(sections: &Map<String, Value>, key: &str) -> Option<T> {
+    let value = sections.get(key)?;
+    match serde_json::from_value(value.clone()) {
+        Ok(section) => Some(section),
+        Err(e) => {
+            debug!(section = key, error = ?e, "failed to parse status section");
+            None
+        }
+    }
+}
+
+/// General device information, from `Status`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct StatusDevice {
+    pub module: u16,
+    pub device_name: String,
+    pub friendly_name: Vec<String>,
+    pub topic: String,
+    pub button_topic: String,
+    pub power_on_state: u8,
+}
+
+/// Device parameters, from `StatusPRM`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct StatusParameters {
+    pub group_topic: String,
+    pub ota_url: String,
+    pub restart_reason: String,
+    pub uptime: String,
+    #[serde(rename = "StartupUTC")]
+    pub startup_utc: String,
+    pub boot_count: u32,
+    pub save_count: u32,
+}
+
+/// Firmware information, from `StatusFWR`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct StatusFirmware {
+    pub version: String,
+    pub build_date_time: String,
+    pub core: String,
+    #[serde(rename = "SDK")]
+    pub sdk: String,
+    pub cpu_frequency: u32,
+    pub hardware: String,
+}
+
+/// Memory information, from `StatusMEM`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct StatusMemory {
+    /// Size of the firmware in kB
+    pub program_size: u32,
+    /// Free program space in kB
+    pub free: u32,
+    /// Free heap in kB
+    pub heap: u32,
+    /// Flash size in kB
+    pub flash_size: u32,
+}
+
+/// Network information, from `StatusNET`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct StatusNetwork {
+    pub hostname: String,
+    #[serde(rename = "IPAddress")]
+    pub ip_address: String,
+    pub gateway: String,
+    pub subnetmask: String,
+    pub mac: String,
+}
+
+/// Mqtt configuration, from `StatusMQT`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct StatusMqtt {
+    pub mqtt_host: String,
+    pub mqtt_port: u16,
+    pub mqtt_client: String,
+    pub mqtt_user: String,
+    pub mqtt_count: u32,
+    #[serde(rename = "KEEPALIVE")]
+    pub keep_alive: u16,
+}
+
+/// Time information, from `StatusTIM`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct StatusTime {
+    #[serde(rename = "UTC")]
+    pub utc: String,
+    pub local: String,
+    pub timezone: Value,
+    pub sunrise: String,
+    pub sunset: String,
+}
+
+/// Current device state, from `StatusSTS`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct StatusState {
+    pub uptime: String,
+    pub uptime_sec: u64,
+    /// Free heap in kB
+    pub heap: u32,
+    pub load_avg: u32,
+    pub mqtt_count: u32,
+    pub wifi: StatusWifi,
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+impl StatusState {
+    /// State of all relays, by relay index starting at 1
+    pub fn power_states(&self) -> BTreeMap<u8, PowerState> {
+        power_states(&self.other)
+    }
+}
+
+/// Collect the relay states from the `POWER`, `POWER1`, `POWER2`, ... keys of a reply
+pub(crate) fn power_states(reply: &Map<String, Value>) -> BTreeMap<u8, PowerState> {
+    reply
+        .iter()
+        .filter_map(|(key, value)| {
+            let index = key.strip_prefix("POWER")?;
+            let index = if index.is_empty() {
+                1
+            } else {
+                index.parse().ok()?
+            };
+            let state = PowerState::deserialize(value).ok()?;
+            Some((index, state))
+        })
+        .collect()
+}
+
+/// Wifi connection details, part of `StatusSTS`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct StatusWifi {
+    #[serde(rename = "SSId")]
+    pub ssid: String,
+    #[serde(rename = "BSSId")]
+    pub bssid: String,
+    pub channel: u8,
+    /// Signal quality in percent
+    #[serde(rename = "RSSI")]
+    pub rssi: u8,
+    /// Signal strength in dBm
+    pub signal: i16,
+    pub link_count: u32,
+}
+
+impl TasmotaClient {
+    /// Get the full status of the device
+    ///
+    /// Sends `Status 0` and collects all status sections the device replies with.
+    /// Fails with [`Error::Timeout`] if not all sections arrive in time, use [`status_partial`](Self::status_partial)
+    /// to get the sections that did arrive instead.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let status = client.status("tasmota_device").await?;
+    /// if let Some(firmware) = status.firmware {
+    ///     println!("firmware: {}", firmware.version);
+    /// }
+    /// if let Some(state) = status.state {
+    ///     println!("signal: {}dBm", state.wifi.signal);
+    /// }
+    ///     # Ok(())
+    /// # }
+    /// ```
+    #[tracing::instrument(skip(self))]
+    pub async fn status(&self, device: &str) -> Result<DeviceStatus> {
+        let (sections, complete) = self.collect_status(device).await?;
+        if !complete {
+            return Err(Error::Timeout);
+        }
+        Ok(DeviceStatus::from_sections(&sections))
+    }
+
+    /// Get the status of the device, leaving out any sections that didn't arrive in time
+    #[tracing::instrument(skip(self))]
+    pub async fn status_partial(&self, device: &str) -> Result<DeviceStatus> {
+        let (sections, _) = self.collect_status(device).await?;
+        Ok(DeviceStatus::from_sections(&sections))
+    }
+
+    /// Send `Status 0` and merge the replies, returning whether all sections were received
+    async fn collect_status(&self, device: &str) -> Result<(Map<String, Value>, bool)> {
+        let _guard = self.command_lock(device).await;
+
+        // depending on the firmware version, the sections are either sent as separate `STATUS<n>`
+        // messages or combined in a single `STATUS0` message
+        let topics = self.topic_scheme(device);
+        let mut rx = self
+            .mqtt
+            .subscribe(
+                topics.topic(TopicPrefix::Stat, device, "+"),
+                20,
+                OverflowPolicy::DropNewest,
+            )
+            .await?;
+        self.mqtt
+            .send_str(&topics.topic(TopicPrefix::Command, device, "Status"), "0")
+            .await?;
+
+        let mut sections = Map::new();
+        let collect = async {
+            while !STATUS_SECTIONS
+                .iter()
+                .all(|key| sections.contains_key(*key))
+            {
+                let msg = rx.recv().await?;
+                if !msg
+                    .topic
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default()
+                    .starts_with("STATUS")
+                {
+                    continue;
+                }
+                let Ok(response) =
+                    serde_json::from_slice::<Map<String, Value>>(msg.payload.as_ref())
+                else {
+                    continue;
+                };
+                sections.extend(response);
+            }
+            Ok::<_, Error>(())
+        };
+
+        let complete = match timeout(self.timeout, collect).await {
+            Ok(result) => {
+                result?;
+                true
+            }
+            Err(_) => {
+                debug!(received = ?sections.keys().collect::<Vec<_>>(), "timeout while waiting for status");
+                false
+            }
+        };
+        Ok((sections, complete))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Replies to `Status 0` from a two relay device, as separate `STATUS<n>` messages
+    fn status_messages() -> Vec<Value> {
+        vec![
+            json!({"Status":{"Module":0,"DeviceName":"Kitchen","FriendlyName":["Kitchen","Kitchen2"],"Topic":"kitchen_plug","ButtonTopic":"0","Power":"10","PowerOnState":3,"LedState":1}}),
+            json!({"StatusPRM":{"Baudrate":115200,"SerialConfig":"8N1","GroupTopic":"tasmotas","OtaUrl":"http://ota.tasmota.com/tasmota/release/tasmota.bin.gz","RestartReason":"Software/System restart","Uptime":"0T00:12:04","StartupUTC":"2024-03-01T10:00:00","Sleep":50,"CfgHolder":4617,"BootCount":17,"BCResetTime":"2023-01-01T12:00:00","SaveCount":120}}),
+            json!({"StatusFWR":{"Version":"13.1.0(tasmota)","BuildDateTime":"2023-08-14T10:32:10","Boot":31,"Core":"2_7_4_9","SDK":"2.2.2-dev(38a443e)","CpuFrequency":80,"Hardware":"ESP8266EX","CR":"400/699"}}),
+            json!({"StatusNET":{"Hostname":"tasmota-AB12CD-3456","IPAddress":"192.168.1.20","Gateway":"192.168.1.1","Subnetmask":"255.255.255.0","DNSServer1":"192.168.1.1","Mac":"A4:CF:12:AB:12:CD","Webserver":2,"HTTP_API":1,"WifiConfig":4,"WifiPower":17.0}}),
+            json!({"StatusSTS":{"Time":"2024-03-01T11:12:04","Uptime":"0T00:12:04","UptimeSec":724,"Heap":25,"SleepMode":"Dynamic","Sleep":50,"LoadAvg":19,"MqttCount":1,"POWER1":"ON","POWER2":"OFF","Wifi":{"AP":1,"SSId":"home-wifi","BSSId":"11:22:33:44:55:66","Channel":6,"Mode":"11n","RSSI":78,"Signal":-61,"LinkCount":1,"Downtime":"0T00:00:03"}}}),
+        ]
+    }
+
+    #[test]
+    fn merge_sections() {
+        let mut sections = Map::new();
+        for message in status_messages() {
+            let Value::Object(message) = message else {
+                unreachable!()
+            };
+            sections.extend(message);
+        }
+        let status = DeviceStatus::from_sections(&sections);
+
+        let device = status.device.unwrap();
+        assert_eq!(device.device_name, "Kitchen");
+        assert_eq!(device.friendly_name, ["Kitchen", "Kitchen2"]);
+        assert_eq!(device.power_on_state, 3);
+        assert_eq!(status.parameters.unwrap().boot_count, 17);
+        let firmware = status.firmware.unwrap();
+        assert_eq!(firmware.version, "13.1.0(tasmota)");
+        assert_eq!(firmware.hardware, "ESP8266EX");
+        assert_eq!(status.network.unwrap().ip_address, "192.168.1.20");
+        let state = status.state.unwrap();
+        assert_eq!(state.uptime_sec, 724);
+        assert_eq!(state.wifi.signal, -61);
+        assert_eq!(
+            state.power_states(),
+            BTreeMap::from([(1, PowerState::On), (2, PowerState::Off)])
+        );
+        // sections that weren't received
+        assert!(status.memory.is_none());
+        assert!(status.mqtt.is_none());
+        assert!(status.time.is_none());
+        assert!(status.sensors.is_none());
+    }
+
+    #[test]
+    fn skip_invalid_section() {
+        let Value::Object(sections) = json!({
+            "Status": {"DeviceName": "Kitchen", "Module": "invalid"},
+            "StatusFWR": {"Version": "13.1.0(tasmota)"},
+        }) else {
+            unreachable!()
+        };
+        let status = DeviceStatus::from_sections(&sections);
+        assert!(status.device.is_none());
+        assert_eq!(status.firmware.unwrap().version, "13.1.0(tasmota)");
+    }
+
+    #[test]
+    fn power_state_keys() {
+        let Value::Object(reply) = json!({"POWER": "ON", "Dimmer": 40, "PowerOnState": 3}) else {
+            unreachable!()
+        };
+        assert_eq!(power_states(&reply), BTreeMap::from([(1, PowerState::On)]));
+
+        let Value::Object(reply) = json!({
+            "POWER1": "OFF",
+            "POWER3": "ON",
+            "POWERX": "ON",
+            "POWER4": "unknown",
+        }) else {
+            unreachable!()
+        };
+        assert_eq!(
+            power_states(&reply),
+            BTreeMap::from([(1, PowerState::Off), (3, PowerState::On)])
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use crate::commands::PowerState;
+use crate::energy::EnergyReading;
+use crate::error::MqttError;
+use crate::mqtt::{Delivery, OverflowPolicy};
+use crate::status::{power_states, StatusWifi};
+use crate::{Result, TasmotaClient, TopicPrefix};
+use async_stream::try_stream;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use tokio_stream::Stream;
+use tracing::debug;
+
+/// A telemetry message periodically published by a device
+///
+/// See also [`TasmotaClient::telemetry`](crate::TasmotaClient::telemetry).
+#[derive(Debug, Clone)]
+pub enum Telemetry {
+    /// Device state, from `tele/<topic>/STATE`
+    State {
+        device: String,
+        state: StateTelemetry,
+    },
+    /// Sensor readings, from `tele/<topic>/SENSOR`
+    Sensor {
+        device: String,
+        sensors: SensorTelemetry,
+    },
+}
+
+impl Telemetry {
+    /// Topic of the device that sent the telemetry
+    pub fn device(&self) -> &str {
+        match self {
+            Telemetry::State { device, .. } | Telemetry::Sensor { device, .. } => device,
+        }
+    }
+
+    fn parse(device: &str, name: &str, payload: &[u8]) -> Option<Self> {
+        let telemetry = match name {
+            "STATE" => serde_json::from_slice(payload).map(|state| Telemetry::State {
+                device: device.into(),
+                state,
+            }),
+            "SENSOR" => serde_json::from_slice(payload).map(|sensors| Telemetry::Sensor {
+                device: device.into(),
+                sensors,
+            }),
+            _ => return None,
+        };
+        match telemetry {
+            Ok(telemetry) => Some(telemetry),
+            Err(e) => {
+                debug!(device, name, error = ?e, "failed to parse telemetry");
+                None
+            }
+        }
+    }
+}
+
+/// Periodic device state
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct StateTelemetry {
+    pub time: String,
+    pub uptime: String,
+    pub uptime_sec: u64,
+    /// Free heap in kB
+    pub heap: u32,
+    pub load_avg: u32,
+    pub mqtt_count: u32,
+    pub wifi: StatusWifi,
+    /// Brightness of a light in percent
+    pub dimmer: Option<u8>,
+    /// Color of a light as hex string
+    pub color: Option<String>,
+    /// Color of a light as hue, saturation and brightness
+    #[serde(rename = "HSBColor")]
+    pub hsb_color: Option<String>,
+    /// Color temperature of a light in mireds
+    #[serde(rename = "CT")]
+    pub ct: Option<u16>,
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+impl StateTelemetry {
+    /// State of all relays, by relay index starting at 1
+    pub fn power_states(&self) -> BTreeMap<u8, PowerState> {
+        power_states(&self.other)
+    }
+}
+
+/// Periodic sensor readings
+#[derive(Debug, Clone, Default)]
+pub struct SensorTelemetry {
+    pub time: String,
+    /// Unit used for all temperatures, `C` or `F`
+    pub temp_unit: Option<String>,
+    /// Readings by sensor name, like `DS18B20-1` or `ENERGY`
+    pub readings: BTreeMap<String, SensorReading>,
+}
+
+impl<'de> Deserialize<'de> for SensorTelemetry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fields = Map::deserialize(deserializer)?;
+        let time = match fields.remove("Time") {
+            Some(Value::String(time)) => time,
+            _ => String::new(),
+        };
+        let temp_unit = match fields.remove("TempUnit") {
+            Some(Value::String(unit)) => Some(unit),
+            _ => None,
+        };
+        let readings = fields
+            .into_iter()
+            .map(|(name, value)| {
+                let reading = SensorReading::parse(&name, value);
+                (name, reading)
+            })
+            .collect();
+        Ok(SensorTelemetry {
+            time,
+            temp_unit,
+            readings,
+        })
+    }
+}
+
+/// Reading of a single sensor
+///
+/// Common sensors are parsed into their own type, all other sensors are kept as raw json.
+#[derive(Debug, Clone)]
+pub enum SensorReading {
+    Ds18b20(TemperatureReading),
+    Am2301(ClimateReading),
+    Bme280(ClimateReading),
+    Energy(EnergyReading),
+    Other(Value),
+}
+
+impl SensorReading {
+    fn parse(name: &str, value: Value) -> Self {
+        // multiple sensors of the same type are numbered like `DS18B20-1`
+        let kind = name.split_once('-').map_or(name, |(kind, _)| kind);
+        let reading = match kind {
+            "DS18B20" => TemperatureReading::deserialize(&value).map(SensorReading::Ds18b20),
+            "AM2301" => ClimateReading::deserialize(&value).map(SensorReading::Am2301),
+            "BME280" => ClimateReading::deserialize(&value).map(SensorReading::Bme280),
+            "ENERGY" => EnergyReading::deserialize(&value).map(SensorReading::Energy),
+            _ => return SensorReading::Other(value),
+        };
+        reading.unwrap_or_else(|e| {
+            debug!(sensor = name, error = ?e, "failed to parse sensor reading");
+            SensorReading::Other(value)
+        })
+    }
+
+    /// The temperature measured by the sensor, if it measures temperature
+    pub fn temperature(&self) -> Option<f64> {
+        match self {
+            SensorReading::Ds18b20(reading) => Some(reading.temperature),
+            SensorReading::Am2301(reading) | SensorReading::Bme280(reading) => {
+                Some(reading.temperature)
+            }
+            SensorReading::Energy(_) => None,
+            SensorReading::Other(value) => value.get("Temperature")?.as_f64(),
+        }
+    }
+}
+
+/// Reading of a temperature sensor
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TemperatureReading {
+    /// Address of the sensor on the bus
+    pub id: Option<String>,
+    pub temperature: f64,
+}
+
+/// Reading of a combined temperature, humidity and optionally pressure sensor
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ClimateReading {
+    pub temperature: f64,
+    /// Relative humidity in percent
+    pub humidity: Option<f64>,
+    pub dew_point: Option<f64>,
+    /// Air pressure in hPa
+    pub pressure: Option<f64>,
+}
+
+impl TasmotaClient {
+    /// Receive the telemetry published by devices
+    ///
+    /// The filter is either the topic of a single device, or `+` to receive the telemetry of all devices
+    /// using the topic layout of the client. The stream ends when the client is shut down.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use std::pin::pin;
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient, Telemetry};
+    /// # use tokio_stream::StreamExt;
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let mut telemetry = pin!(client.telemetry("+"));
+    /// while let Some(telemetry) = telemetry.next().await {
+    ///     if let Telemetry::Sensor { device, sensors } = telemetry? {
+    ///         for (name, reading) in sensors.readings {
+    ///             if let Some(temperature) = reading.temperature() {
+    ///                 println!("{device} {name}: {temperature}");
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    ///     # Ok(())
+    /// # }
+    /// ```
+    pub fn telemetry<'a>(
+        &'a self,
+        device_filter: &'a str,
+    ) -> impl Stream<Item = Result<Telemetry>> + 'a {
+        try_stream! {
+            let topics = self.topic_scheme(device_filter);
+            let mut rx = self
+                .mqtt
+                .subscribe(
+                    topics.topic(TopicPrefix::Tele, device_filter, "+"),
+                    100,
+                    OverflowPolicy::DropOldest,
+                )
+                .await?;
+
+            loop {
+                let msg = match rx.next_delivery().await {
+                    Ok(Delivery::Message(msg)) => msg,
+                    Ok(Delivery::Lagged(count)) => {
+                        debug!(count, "missed telemetry messages");
+                        continue;
+                    }
+                    Err(MqttError::Shutdown) => break,
+                    Err(e) => Err(e)?,
+                };
+                let Some((TopicPrefix::Tele, device, name)) = topics.parse(&msg.topic) else {
+                    continue;
+                };
+                if let Some(telemetry) = Telemetry::parse(device, name, msg.payload.as_ref()) {
+                    yield telemetry;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sensors(payload: &str) -> SensorTelemetry {
+        match Telemetry::parse("kitchen_plug", "SENSOR", payload.as_bytes()) {
+            Some(Telemetry::Sensor { device, sensors }) => {
+                assert_eq!(device, "kitchen_plug");
+                sensors
+            }
+            other => panic!("unexpected telemetry {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_temperature_sensors() {
+        let sensors = sensors(
+            r#"{"Time":"2024-03-01T11:12:04","DS18B20-1":{"Id":"01144A0CB2AA","Temperature":21.4},"DS18B20-2":{"Id":"3C01D075DAE1","Temperature":19.8},"BME280":{"Temperature":22.1,"Humidity":45.3,"DewPoint":9.7,"Pressure":1012.4},"PressureUnit":"hPa","TempUnit":"C"}"#,
+        );
+        assert_eq!(sensors.time, "2024-03-01T11:12:04");
+        assert_eq!(sensors.temp_unit.as_deref(), Some("C"));
+        assert_eq!(sensors.readings.len(), 4);
+
+        let SensorReading::Ds18b20(ds18b20) = &sensors.readings["DS18B20-2"] else {
+            panic!("unexpected reading {:?}", sensors.readings["DS18B20-2"]);
+        };
+        assert_eq!(ds18b20.id.as_deref(), Some("3C01D075DAE1"));
+        assert_eq!(ds18b20.temperature, 19.8);
+        assert_eq!(sensors.readings["DS18B20-1"].temperature(), Some(21.4));
+
+        let SensorReading::Bme280(bme280) = &sensors.readings["BME280"] else {
+            panic!("unexpected reading {:?}", sensors.readings["BME280"]);
+        };
+        assert_eq!(bme280.temperature, 22.1);
+        assert_eq!(bme280.humidity, Some(45.3));
+        assert_eq!(bme280.dew_point, Some(9.7));
+        assert_eq!(bme280.pressure, Some(1012.4));
+
+        // top level fields other than the time and temperature unit are kept as is
+        assert!(matches!(
+            &sensors.readings["PressureUnit"],
+            SensorReading::Other(Value::String(unit)) if unit == "hPa"
+        ));
+        assert_eq!(sensors.readings["PressureUnit"].temperature(), None);
+    }
+
+    #[test]
+    fn parse_energy() {
+        let sensors = sensors(
+            r#"{"Time":"2024-03-01T11:12:04","ENERGY":{"TotalStartTime":"2023-01-01T12:00:00","Total":12.345,"Yesterday":0.512,"Today":0.123,"Period":0,"Power":45,"ApparentPower":50,"ReactivePower":22,"Factor":0.90,"Voltage":231,"Current":0.216}}"#,
+        );
+        assert_eq!(sensors.temp_unit, None);
+        let SensorReading::Energy(energy) = &sensors.readings["ENERGY"] else {
+            panic!("unexpected reading {:?}", sensors.readings["ENERGY"]);
+        };
+        assert_eq!(
+            energy.total_start_time.as_deref(),
+            Some("2023-01-01T12:00:00")
+        );
+        assert_eq!(energy.total, 12.345);
+        assert_eq!(energy.today, 0.123);
+        assert_eq!(energy.power, 45.0);
+        assert_eq!(energy.factor, Some(0.9));
+        assert_eq!(energy.current, Some(0.216));
+        assert_eq!(sensors.readings["ENERGY"].temperature(), None);
+    }
+
+    #[test]
+    fn keep_unparsable_readings() {
+        // a sensor that failed to read reports null values
+        let sensors = sensors(
+            r#"{"Time":"2024-03-01T11:12:04","AM2301":{"Temperature":null,"Humidity":null,"DewPoint":null},"SHT3X-0x44":{"Temperature":71.2,"Humidity":48.0,"DewPoint":50.6},"TempUnit":"F"}"#,
+        );
+        assert_eq!(sensors.temp_unit.as_deref(), Some("F"));
+        assert!(matches!(
+            sensors.readings["AM2301"],
+            SensorReading::Other(_)
+        ));
+        assert_eq!(sensors.readings["AM2301"].temperature(), None);
+
+        // unknown sensors still expose their temperature
+        assert!(matches!(
+            sensors.readings["SHT3X-0x44"],
+            SensorReading::Other(_)
+        ));
+        assert_eq!(sensors.readings["SHT3X-0x44"].temperature(), Some(71.2));
+    }
+
+    #[test]
+    fn ignore_other_telemetry() {
+        assert!(Telemetry::parse("kitchen_plug", "LWT", b"Online").is_none());
+        assert!(Telemetry::parse("kitchen_plug", "SENSOR", b"Online").is_none());
+    }
+}
@@ -0,0 +1,380 @@
+//! Device templates, base modules and gpio assignments
+
+use crate::commands::{self, Module, Status, TasmotaCommand, TemplateResponse};
+use crate::error::Error;
+use crate::mqtt::OverflowPolicy;
+use crate::status::StatusFirmware;
+use crate::{split_index, Result, TasmotaClient, TopicPrefix};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::time::{timeout, Instant};
+
+/// Time to wait for further parts of the supported component list
+const GPIO_COMPONENTS_WAIT: Duration = Duration::from_millis(500);
+
+/// Component id for an unused pin
+pub const GPIO_NONE: u16 = 0;
+/// Component id for a pin that can be configured with the `GPIO` command
+pub const GPIO_USER: u16 = 1;
+
+/// The chip family of a device, which determines the layout of its template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    Esp8266,
+    Esp32,
+    Esp32C3,
+    Esp32S2,
+    Esp32S3,
+}
+
+impl Chip {
+    /// Determine the chip from the hardware reported in `StatusFWR`, like `ESP8266EX`, `ESP32-D0WD-V3` or `ESP32-C3`
+    pub fn from_hardware(hardware: &str) -> Option<Self> {
+        let hardware = hardware.to_ascii_uppercase();
+        if hardware.starts_with("ESP8266") || hardware.starts_with("ESP8285") {
+            Some(Chip::Esp8266)
+        } else if hardware.starts_with("ESP32-C3") {
+            Some(Chip::Esp32C3)
+        } else if hardware.starts_with("ESP32-S2") {
+            Some(Chip::Esp32S2)
+        } else if hardware.starts_with("ESP32-S3") {
+            Some(Chip::Esp32S3)
+        } else if ["ESP32-C2", "ESP32-C5", "ESP32-C6", "ESP32-H2", "ESP32-P4"]
+            .iter()
+            .any(|variant| hardware.starts_with(variant))
+        {
+            // other variants have a different template layout that isn't known here
+            None
+        } else if hardware.starts_with("ESP32") {
+            Some(Chip::Esp32)
+        } else {
+            None
+        }
+    }
+
+    /// Number of pins in a template for this chip
+    pub fn template_pins(&self) -> usize {
+        match self {
+            // GPIO0-5, GPIO9-10, GPIO12-16 and the analog input
+            Chip::Esp8266 => 14,
+            Chip::Esp32 => 36,
+            Chip::Esp32C3 => 22,
+            Chip::Esp32S2 => 36,
+            Chip::Esp32S3 => 49,
+        }
+    }
+}
+
+/// A device template, defining the function of every pin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pub name: String,
+    /// Component id for every pin in the template
+    pub gpio: Vec<u16>,
+    pub flag: u16,
+    /// Module the template is based on
+    pub base: u8,
+}
+
+impl Template {
+    /// Check that the template matches the layout of the chip and only uses supported components
+    ///
+    /// The supported components differ between ESP8266 and ESP32 firmware, they are listed by
+    /// [`TasmotaClient::gpio_components`](crate::TasmotaClient::gpio_components).
+    pub fn validate(&self, chip: Chip, components: &BTreeMap<u16, String>) -> Result<(), Error> {
+        if self.gpio.len() != chip.template_pins() {
+            return Err(Error::InvalidTemplate(format!(
+                "{:?} templates have {} pins, but the template has {}",
+                chip,
+                chip.template_pins(),
+                self.gpio.len()
+            )));
+        }
+        // component ids are made of the component type times 32 plus the index of the component
+        let unsupported = self.gpio.iter().find(|id| {
+            **id != GPIO_NONE
+                && **id != GPIO_USER
+                && !components
+                    .keys()
+                    .any(|supported| supported & !31 == **id & !31)
+        });
+        if let Some(id) = unsupported {
+            return Err(Error::InvalidTemplate(format!(
+                "component id {id} is not supported by the {chip:?} firmware"
+            )));
+        }
+        if self.base == 0 {
+            return Err(Error::InvalidTemplate("base module can't be 0".into()));
+        }
+        Ok(())
+    }
+
+    /// The json template accepted by the `Template` command
+    fn to_json(&self) -> String {
+        json!({
+            "NAME": self.name,
+            "GPIO": self.gpio,
+            "FLAG": self.flag,
+            "BASE": self.base,
+        })
+        .to_string()
+    }
+}
+
+impl From<TemplateResponse> for Template {
+    fn from(response: TemplateResponse) -> Self {
+        Template {
+            name: response.name,
+            gpio: response.gpio,
+            flag: response.flag,
+            base: response.base,
+        }
+    }
+}
+
+/// The component assigned to a pin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpioAssignment {
+    pub component: u16,
+    /// Name of the component, like `Relay1` or `None`
+    pub name: String,
+}
+
+/// Get the components assigned to the configurable pins
+#[derive(Debug, Clone)]
+pub struct Gpio;
+
+/// Reply to [`Gpio`], the component id and name for every `GPIO<pin>`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct GpioResponse(pub BTreeMap<String, BTreeMap<String, String>>);
+
+impl GpioResponse {
+    /// The assigned components by pin number
+    pub fn assignments(&self) -> BTreeMap<u8, GpioAssignment> {
+        self.0
+            .iter()
+            .filter_map(|(pin, component)| {
+                let pin = pin.strip_prefix("GPIO")?.parse().ok()?;
+                let (id, name) = component.iter().next()?;
+                Some((
+                    pin,
+                    GpioAssignment {
+                        component: id.parse().ok()?,
+                        name: name.clone(),
+                    },
+                ))
+            })
+            .collect()
+    }
+}
+
+impl TasmotaCommand for Gpio {
+    type Response = GpioResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "GPIO".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        "".into()
+    }
+}
+
+impl TasmotaClient {
+    /// Get the template of a device
+    #[tracing::instrument(skip(self))]
+    pub async fn template(&self, device: &str) -> Result<Template> {
+        Ok(self
+            .execute(device, &commands::Template(None))
+            .await?
+            .into())
+    }
+
+    /// Validate and activate a template, returning once the device is back online
+    ///
+    /// The template is checked against the chip of the device and the components supported by its firmware.
+    /// Activating the template restarts the device, after which the stored template is compared to the one sent.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// # use tasmota_mqtt_client::template::Template;
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let template = Template {
+    ///     name: "Sonoff Basic".into(),
+    ///     gpio: vec![32, 1, 1, 1, 1, 0, 0, 0, 224, 320, 1, 0, 0, 0],
+    ///     flag: 0,
+    ///     base: 1,
+    /// };
+    /// client.set_template("tasmota_device", &template).await?;
+    ///     # Ok(())
+    /// # }
+    /// ```
+    #[tracing::instrument(skip(self))]
+    pub async fn set_template(&self, device: &str, template: &Template) -> Result<Template> {
+        let firmware = self.execute(device, &Status(2)).await?.0;
+        let hardware = StatusFirmware::deserialize(&firmware)?.hardware;
+        let chip = Chip::from_hardware(&hardware)
+            .ok_or_else(|| Error::InvalidTemplate(format!("unknown hardware {hardware}")))?;
+        let components = self.gpio_components(device).await?;
+        template.validate(chip, &components)?;
+
+        let module = self.module(device).await?;
+
+        let deadline = Instant::now() + self.restart_timeout;
+        let mut device_update = self.device_update.subscribe();
+        let mut boot = self
+            .boot_subscription(&self.topic_scheme(device), device)
+            .await?;
+        // a device already using its template restarts when the template changes, otherwise switching
+        // to module 0 activates the template and restarts the device
+        self.execute(device, &commands::Template(Some(template.to_json())))
+            .await?;
+        if module != 0 {
+            self.execute(device, &Module(Some(0))).await?;
+        }
+        self.wait_restarted(device, &mut device_update, &mut boot, deadline)
+            .await?;
+
+        let stored = self.template(device).await?;
+        if stored != *template {
+            return Err(Error::ConfigurationMismatch("template"));
+        }
+        Ok(stored)
+    }
+
+    /// Get the base module of a device, 0 if the device uses its template
+    #[tracing::instrument(skip(self))]
+    pub async fn module(&self, device: &str) -> Result<u8> {
+        let response = self.execute(device, &Module(None)).await?;
+        response
+            .module
+            .keys()
+            .next()
+            .and_then(|module| module.parse().ok())
+            .ok_or_else(|| Error::MalformedReply("module", format!("{:?}", response.module)))
+    }
+
+    /// Set the base module of a device, returning once the device is back online with the new module
+    #[tracing::instrument(skip(self))]
+    pub async fn set_module(&self, device: &str, module: u8) -> Result<u8> {
+        if self.module(device).await? == module {
+            return Ok(module);
+        }
+        let deadline = Instant::now() + self.restart_timeout;
+        let mut device_update = self.device_update.subscribe();
+        let mut boot = self
+            .boot_subscription(&self.topic_scheme(device), device)
+            .await?;
+        self.execute(device, &Module(Some(module))).await?;
+        self.wait_restarted(device, &mut device_update, &mut boot, deadline)
+            .await?;
+
+        if self.module(device).await? != module {
+            return Err(Error::ConfigurationMismatch("module"));
+        }
+        Ok(module)
+    }
+
+    /// Get the components assigned to the configurable pins of a device, by pin number
+    #[tracing::instrument(skip(self))]
+    pub async fn gpio(&self, device: &str) -> Result<BTreeMap<u8, GpioAssignment>> {
+        Ok(self.execute(device, &Gpio).await?.assignments())
+    }
+
+    /// Get the components supported by the firmware of a device, by component id
+    ///
+    /// Only the first component of every type is listed, the ids of further components follow it.
+    #[tracing::instrument(skip(self))]
+    pub async fn gpio_components(&self, device: &str) -> Result<BTreeMap<u16, String>> {
+        let _guard = self.command_lock(device).await;
+
+        // the list is split over multiple `GPIOs<n>` replies, without marking the last one
+        let topics = self.topic_scheme(device);
+        let mut rx = self
+            .mqtt
+            .subscribe(
+                topics.topic(TopicPrefix::Stat, device, "RESULT"),
+                20,
+                OverflowPolicy::DropNewest,
+            )
+            .await?;
+        self.mqtt
+            .send_str(&topics.topic(TopicPrefix::Command, device, "GPIOs"), "")
+            .await?;
+
+        let mut components = BTreeMap::new();
+        let mut wait = self.timeout;
+        loop {
+            let msg = match timeout(wait, rx.recv()).await {
+                Ok(msg) => msg?,
+                Err(_) if components.is_empty() => return Err(Error::Timeout),
+                Err(_) => return Ok(components),
+            };
+            let Ok(reply) = serde_json::from_slice::<Map<String, Value>>(msg.payload.as_ref())
+            else {
+                continue;
+            };
+            for (key, list) in reply {
+                if split_index(&key).0 != "GPIOs" {
+                    continue;
+                }
+                let list = BTreeMap::<String, String>::deserialize(list)?;
+                components.extend(
+                    list.into_iter()
+                        .filter_map(|(id, name)| Some((id.parse().ok()?, name))),
+                );
+                // further parts follow directly
+                wait = GPIO_COMPONENTS_WAIT;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chip_from_hardware() {
+        assert_eq!(Chip::from_hardware("ESP8266EX"), Some(Chip::Esp8266));
+        assert_eq!(Chip::from_hardware("ESP8285N08"), Some(Chip::Esp8266));
+        assert_eq!(Chip::from_hardware("ESP32-D0WD-V3"), Some(Chip::Esp32));
+        assert_eq!(Chip::from_hardware("ESP32-PICO-D4"), Some(Chip::Esp32));
+        assert_eq!(Chip::from_hardware("ESP32-C3"), Some(Chip::Esp32C3));
+        assert_eq!(Chip::from_hardware("ESP32-S2"), Some(Chip::Esp32S2));
+        assert_eq!(Chip::from_hardware("ESP32-S3"), Some(Chip::Esp32S3));
+        assert_eq!(Chip::from_hardware("ESP32-C6"), None);
+        assert_eq!(Chip::from_hardware("RP2040"), None);
+    }
+
+    #[test]
+    fn validate() {
+        let components = BTreeMap::from([(32, "Button1".into()), (224, "Relay1".into())]);
+        let mut template = Template {
+            name: "Sonoff Basic".into(),
+            gpio: vec![32, 1, 1, 1, 1, 0, 0, 0, 224, 320, 1, 0, 0, 0],
+            flag: 0,
+            base: 1,
+        };
+        // led 320 isn't supported
+        assert!(template.validate(Chip::Esp8266, &components).is_err());
+        template.gpio[9] = 225;
+        assert!(template.validate(Chip::Esp8266, &components).is_ok());
+        assert!(template.validate(Chip::Esp32C3, &components).is_err());
+        template.base = 0;
+        assert!(template.validate(Chip::Esp8266, &components).is_err());
+    }
+}
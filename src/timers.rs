@@ -0,0 +1,763 @@
+//! Typed timer schedules and the location and timezone settings they depend on
+
+use crate::commands::{self, on_off, single_value, TasmotaCommand, TimerInfo};
+use crate::error::Error;
+use crate::mqtt::OverflowPolicy;
+use crate::{split_index, Result, TasmotaClient, TopicPrefix};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::ops::BitOr;
+use tokio::time::timeout;
+
+/// Number of timers of a device
+pub const TIMER_COUNT: u8 = 16;
+
+/// Set of weekdays a timer is active on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Days(u8);
+
+impl Days {
+    pub const SUNDAY: Days = Days(1);
+    pub const MONDAY: Days = Days(1 << 1);
+    pub const TUESDAY: Days = Days(1 << 2);
+    pub const WEDNESDAY: Days = Days(1 << 3);
+    pub const THURSDAY: Days = Days(1 << 4);
+    pub const FRIDAY: Days = Days(1 << 5);
+    pub const SATURDAY: Days = Days(1 << 6);
+    pub const WEEKDAYS: Days = Days(0b0111110);
+    pub const WEEKEND: Days = Days(0b1000001);
+    pub const ALL: Days = Days(0b1111111);
+    pub const NONE: Days = Days(0);
+
+    /// Create the set from a bitmask, with sunday in the lowest bit
+    pub fn from_bits(bits: u8) -> Self {
+        Days(bits & Self::ALL.0)
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(&self, days: Days) -> bool {
+        self.0 & days.0 == days.0
+    }
+
+    /// Parse the `SMTWTFS` format used by tasmota, where `-` or `0` marks an inactive day
+    fn parse(days: &str) -> Option<Self> {
+        if days.chars().count() != 7 {
+            return None;
+        }
+        Some(Days(days.chars().enumerate().fold(0, |bits, (i, day)| {
+            if day == '-' || day == '0' {
+                bits
+            } else {
+                bits | 1 << i
+            }
+        })))
+    }
+}
+
+impl BitOr for Days {
+    type Output = Days;
+
+    fn bitor(self, rhs: Days) -> Days {
+        Days(self.0 | rhs.0)
+    }
+}
+
+impl Display for Days {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, day) in "SMTWTFS".chars().enumerate() {
+            let day = if self.0 & 1 << i != 0 { day } else { '-' };
+            write!(f, "{day}")?;
+        }
+        Ok(())
+    }
+}
+
+/// When a timer triggers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerSchedule {
+    /// At a fixed time of day
+    Time { hour: u8, minute: u8 },
+    /// Relative to sunrise, the offset is in minutes and can be negative
+    Sunrise { offset: i16 },
+    /// Relative to sunset, the offset is in minutes and can be negative
+    Sunset { offset: i16 },
+}
+
+impl TimerSchedule {
+    fn mode(&self) -> u8 {
+        match self {
+            TimerSchedule::Time { .. } => 0,
+            TimerSchedule::Sunrise { .. } => 1,
+            TimerSchedule::Sunset { .. } => 2,
+        }
+    }
+
+    fn time(&self) -> String {
+        match *self {
+            TimerSchedule::Time { hour, minute } => format!("{hour:02}:{minute:02}"),
+            TimerSchedule::Sunrise { offset } | TimerSchedule::Sunset { offset } => {
+                format_offset(offset)
+            }
+        }
+    }
+
+    fn parse(mode: u8, time: &str) -> Option<Self> {
+        match mode {
+            0 => {
+                let (hour, minute) = parse_time(time)?;
+                Some(TimerSchedule::Time { hour, minute })
+            }
+            1 => Some(TimerSchedule::Sunrise {
+                offset: parse_offset(time)?,
+            }),
+            2 => Some(TimerSchedule::Sunset {
+                offset: parse_offset(time)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a time of day as `HH:MM` into hours and minutes
+fn parse_time(time: &str) -> Option<(u8, u8)> {
+    let (hours, minutes) = time.split_once(':')?;
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|c| c.is_ascii_digit());
+    if !digits(hours) || !digits(minutes) {
+        return None;
+    }
+    let hours: u8 = hours.parse().ok()?;
+    let minutes: u8 = minutes.parse().ok()?;
+    (hours <= 23 && minutes <= 59).then_some((hours, minutes))
+}
+
+/// Format minutes as `+HH:MM` or `-HH:MM`
+fn format_offset(minutes: i16) -> String {
+    let sign = if minutes < 0 { '-' } else { '+' };
+    let minutes = minutes.unsigned_abs();
+    format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Parse `HH:MM`, optionally prefixed by a sign, into minutes
+fn parse_offset(time: &str) -> Option<i16> {
+    let (sign, time) = match time.strip_prefix('-') {
+        Some(time) => (-1, time),
+        None => (1, time.strip_prefix('+').unwrap_or(time)),
+    };
+    let (hours, minutes) = time.split_once(':')?;
+    let hours: i16 = hours.parse().ok()?;
+    let minutes: i16 = minutes.parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// What a timer does to its output when it triggers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerAction {
+    Off = 0,
+    On = 1,
+    Toggle = 2,
+    /// Trigger the `Clock#Timer=<index>` rule event, or blink the output when rules are disabled
+    Rule = 3,
+}
+
+/// A timer schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timer {
+    pub enabled: bool,
+    pub schedule: TimerSchedule,
+    /// Random window in minutes around the scheduled time, up to 15
+    pub window: u8,
+    pub days: Days,
+    /// Whether the timer stays enabled after triggering
+    pub repeat: bool,
+    /// Relay triggered by the timer, starting at 1
+    pub output: u8,
+    pub action: TimerAction,
+}
+
+impl Timer {
+    /// A repeating timer at a fixed time of day
+    pub fn daily(hour: u8, minute: u8, days: Days, output: u8, action: TimerAction) -> Self {
+        Timer {
+            enabled: true,
+            schedule: TimerSchedule::Time { hour, minute },
+            window: 0,
+            days,
+            repeat: true,
+            output,
+            action,
+        }
+    }
+
+    /// The json timer definition accepted by the `Timer<index>` command
+    fn to_json(self) -> String {
+        json!({
+            "Enable": self.enabled as u8,
+            "Mode": self.schedule.mode(),
+            "Time": self.schedule.time(),
+            "Window": self.window,
+            "Days": self.days.to_string(),
+            "Repeat": self.repeat as u8,
+            "Output": self.output,
+            "Action": self.action as u8,
+        })
+        .to_string()
+    }
+}
+
+impl TryFrom<TimerInfo> for Timer {
+    type Error = Error;
+
+    fn try_from(info: TimerInfo) -> Result<Self, Self::Error> {
+        let malformed = || Error::MalformedReply("timer", format!("{info:?}"));
+        let action = match info.action {
+            0 => TimerAction::Off,
+            1 => TimerAction::On,
+            2 => TimerAction::Toggle,
+            3 => TimerAction::Rule,
+            _ => return Err(malformed()),
+        };
+        Ok(Timer {
+            enabled: info.enable != 0,
+            schedule: TimerSchedule::parse(info.mode, &info.time).ok_or_else(malformed)?,
+            window: info.window,
+            days: Days::parse(&info.days).ok_or_else(malformed)?,
+            repeat: info.repeat != 0,
+            output: info.output,
+            action,
+        })
+    }
+}
+
+/// Get or set whether timers are enabled on the device
+#[derive(Debug, Clone)]
+pub struct Timers(pub Option<bool>);
+
+/// Reply to [`Timers`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimersResponse {
+    #[serde(rename = "Timers", deserialize_with = "on_off")]
+    pub enabled: bool,
+}
+
+impl TasmotaCommand for Timers {
+    type Response = TimersResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "Timers".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        match self.0 {
+            Some(true) => "1".into(),
+            Some(false) => "0".into(),
+            None => "".into(),
+        }
+    }
+}
+
+/// Deserialize a number that tasmota sends either as json number or as string
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Number(number) => number
+            .as_f64()
+            .ok_or_else(|| D::Error::custom(format!("invalid number {number}"))),
+        Value::String(number) => number.parse().map_err(D::Error::custom),
+        value => Err(D::Error::custom(format!("invalid number {value}"))),
+    }
+}
+
+/// Reply to [`Latitude`] and [`Longitude`], the coordinate in degrees
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(transparent)]
+pub struct CoordinateResponse(#[serde(deserialize_with = "coordinate")] pub f64);
+
+fn coordinate<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    #[derive(Deserialize)]
+    struct Coordinate(#[serde(deserialize_with = "number")] f64);
+
+    single_value::<D, Coordinate>(deserializer).map(|coordinate| coordinate.0)
+}
+
+/// Get or set the latitude used for sunrise and sunset timers, in degrees
+#[derive(Debug, Clone)]
+pub struct Latitude(pub Option<f64>);
+
+impl TasmotaCommand for Latitude {
+    type Response = CoordinateResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "Latitude".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+            .into()
+    }
+}
+
+/// Get or set the longitude used for sunrise and sunset timers, in degrees
+#[derive(Debug, Clone)]
+pub struct Longitude(pub Option<f64>);
+
+impl TasmotaCommand for Longitude {
+    type Response = CoordinateResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "Longitude".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+            .into()
+    }
+}
+
+/// The timezone of a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timezone {
+    /// A fixed offset from UTC in minutes
+    Offset(i16),
+    /// Switch between the offsets of [`TimeDst`] and [`TimeStd`]
+    DaylightSaving,
+}
+
+impl<'de> Deserialize<'de> for Timezone {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::Number(number) if number.as_u64() == Some(99) => Ok(Timezone::DaylightSaving),
+            Value::Number(number) => number
+                .as_i64()
+                .map(|hours| Timezone::Offset(hours as i16 * 60))
+                .ok_or_else(|| D::Error::custom(format!("invalid timezone {number}"))),
+            Value::String(offset) => parse_offset(&offset)
+                .map(Timezone::Offset)
+                .ok_or_else(|| D::Error::custom(format!("invalid timezone {offset}"))),
+            value => Err(D::Error::custom(format!("invalid timezone {value}"))),
+        }
+    }
+}
+
+/// Get or set the timezone of the device
+#[derive(Debug, Clone)]
+pub struct SetTimezone(pub Option<Timezone>);
+
+/// Reply to [`SetTimezone`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimezoneResponse {
+    #[serde(rename = "Timezone")]
+    pub timezone: Timezone,
+}
+
+impl TasmotaCommand for SetTimezone {
+    type Response = TimezoneResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "Timezone".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        match self.0 {
+            Some(Timezone::Offset(minutes)) => format_offset(minutes).into(),
+            Some(Timezone::DaylightSaving) => "99".into(),
+            None => "".into(),
+        }
+    }
+}
+
+/// The moment a device switches to daylight saving or standard time, and the offset used after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TimeRule {
+    /// 0 for the northern and 1 for the southern hemisphere
+    pub hemisphere: u8,
+    /// Week of the month, 1 to 4 or 0 for the last week
+    pub week: u8,
+    /// Month, starting at 1 for january
+    pub month: u8,
+    /// Day of the week, starting at 1 for sunday
+    pub day: u8,
+    /// Hour of the switch in local time
+    pub hour: u8,
+    /// Offset from UTC in minutes
+    pub offset: i16,
+}
+
+impl TimeRule {
+    fn payload(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.hemisphere, self.week, self.month, self.day, self.hour, self.offset
+        )
+    }
+}
+
+/// Reply to [`TimeDst`] and [`TimeStd`]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct TimeRuleResponse(#[serde(deserialize_with = "single_value")] pub TimeRule);
+
+/// Get or set the start of daylight saving time
+#[derive(Debug, Clone)]
+pub struct TimeDst(pub Option<TimeRule>);
+
+impl TasmotaCommand for TimeDst {
+    type Response = TimeRuleResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "TimeDST".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0.map(|rule| rule.payload()).unwrap_or_default().into()
+    }
+}
+
+/// Get or set the start of standard time
+#[derive(Debug, Clone)]
+pub struct TimeStd(pub Option<TimeRule>);
+
+impl TasmotaCommand for TimeStd {
+    type Response = TimeRuleResponse;
+
+    fn name(&self) -> Cow<'_, str> {
+        "TimeSTD".into()
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        self.0.map(|rule| rule.payload()).unwrap_or_default().into()
+    }
+}
+
+impl TasmotaClient {
+    /// Get all timers of a device, by timer index starting at 1
+    #[tracing::instrument(skip(self))]
+    pub async fn timers(&self, device: &str) -> Result<BTreeMap<u8, Timer>> {
+        let _guard = self.command_lock(device).await;
+
+        // `Timers` replies with the enabled state, followed by the timers split over four `Timers<n>` replies
+        let topics = self.topic_scheme(device);
+        let mut rx = self
+            .mqtt
+            .subscribe(
+                topics.topic(TopicPrefix::Stat, device, "RESULT"),
+                20,
+                OverflowPolicy::DropNewest,
+            )
+            .await?;
+        self.mqtt
+            .send_str(&topics.topic(TopicPrefix::Command, device, "Timers"), "")
+            .await?;
+
+        let mut timers = BTreeMap::new();
+        let collect = async {
+            while timers.len() < TIMER_COUNT as usize {
+                let msg = rx.recv().await?;
+                let Ok(reply) = serde_json::from_slice::<Map<String, Value>>(msg.payload.as_ref())
+                else {
+                    continue;
+                };
+                for (key, part) in reply {
+                    if !matches!(split_index(&key), ("Timers", index) if !index.is_empty()) {
+                        continue;
+                    }
+                    for (name, info) in BTreeMap::<String, TimerInfo>::deserialize(part)? {
+                        if let ("Timer", index) = split_index(&name) {
+                            if let Ok(index) = index.parse() {
+                                timers.insert(index, info.try_into()?);
+                            }
+                        }
+                    }
+                }
+            }
+            Ok::<_, Error>(())
+        };
+        timeout(self.timeout, collect)
+            .await
+            .map_err(|_| Error::Timeout)??;
+        Ok(timers)
+    }
+
+    /// Get a timer, timers are numbered from 1
+    #[tracing::instrument(skip(self))]
+    pub async fn timer(&self, device: &str, index: u8) -> Result<Timer> {
+        self.execute(
+            device,
+            &commands::Timer {
+                index,
+                payload: None,
+            },
+        )
+        .await?
+        .0
+        .try_into()
+    }
+
+    /// Replace a timer, returning the timer as stored by the device
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use tasmota_mqtt_client::{Result, TasmotaClient};
+    /// # use tasmota_mqtt_client::timers::{Days, Timer, TimerAction};
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    ///     # let client = TasmotaClient::builder("mqtt.example.com", 1883)
+    ///     #     .credentials("mqtt_username", "mqtt_password")
+    ///     #     .connect()
+    ///     #     .await?;
+    /// // let client: TasmotaClient = ...
+    /// let timer = Timer::daily(7, 30, Days::WEEKDAYS, 1, TimerAction::On);
+    /// client.set_timer("tasmota_device", 1, timer).await?;
+    /// client.set_timers_enabled("tasmota_device", true).await?;
+    ///     # Ok(())
+    /// # }
+    /// ```
+    #[tracing::instrument(skip(self))]
+    pub async fn set_timer(&self, device: &str, index: u8, timer: Timer) -> Result<Timer> {
+        self.execute(
+            device,
+            &commands::Timer {
+                index,
+                payload: Some(timer.to_json()),
+            },
+        )
+        .await?
+        .0
+        .try_into()
+    }
+
+    /// Reset a timer to its disabled default, returning the timer as stored by the device
+    #[tracing::instrument(skip(self))]
+    pub async fn clear_timer(&self, device: &str, index: u8) -> Result<Timer> {
+        self.execute(
+            device,
+            &commands::Timer {
+                index,
+                payload: Some("0".into()),
+            },
+        )
+        .await?
+        .0
+        .try_into()
+    }
+
+    /// Get whether timers are enabled on a device
+    #[tracing::instrument(skip(self))]
+    pub async fn timers_enabled(&self, device: &str) -> Result<bool> {
+        Ok(self.execute(device, &Timers(None)).await?.enabled)
+    }
+
+    /// Enable or disable all timers of a device
+    #[tracing::instrument(skip(self))]
+    pub async fn set_timers_enabled(&self, device: &str, enabled: bool) -> Result<bool> {
+        Ok(self.execute(device, &Timers(Some(enabled))).await?.enabled)
+    }
+
+    /// Get the latitude and longitude used for sunrise and sunset timers
+    #[tracing::instrument(skip(self))]
+    pub async fn location(&self, device: &str) -> Result<(f64, f64)> {
+        let latitude = self.execute(device, &Latitude(None)).await?.0;
+        let longitude = self.execute(device, &Longitude(None)).await?.0;
+        Ok((latitude, longitude))
+    }
+
+    /// Set the latitude and longitude used for sunrise and sunset timers, in degrees
+    #[tracing::instrument(skip(self))]
+    pub async fn set_location(&self, device: &str, latitude: f64, longitude: f64) -> Result<()> {
+        self.execute(device, &Latitude(Some(latitude))).await?;
+        self.execute(device, &Longitude(Some(longitude))).await?;
+        Ok(())
+    }
+
+    /// Get the timezone of a device
+    #[tracing::instrument(skip(self))]
+    pub async fn timezone(&self, device: &str) -> Result<Timezone> {
+        Ok(self.execute(device, &SetTimezone(None)).await?.timezone)
+    }
+
+    /// Set the timezone of a device
+    ///
+    /// With [`Timezone::DaylightSaving`], the device switches between the rules set by
+    /// [`set_daylight_saving`](Self::set_daylight_saving).
+    #[tracing::instrument(skip(self))]
+    pub async fn set_timezone(&self, device: &str, timezone: Timezone) -> Result<Timezone> {
+        Ok(self
+            .execute(device, &SetTimezone(Some(timezone)))
+            .await?
+            .timezone)
+    }
+
+    /// Get the rules for the start of daylight saving time and standard time
+    #[tracing::instrument(skip(self))]
+    pub async fn daylight_saving(&self, device: &str) -> Result<(TimeRule, TimeRule)> {
+        let dst = self.execute(device, &TimeDst(None)).await?.0;
+        let std = self.execute(device, &TimeStd(None)).await?.0;
+        Ok((dst, std))
+    }
+
+    /// Set the rules for the start of daylight saving time and standard time
+    #[tracing::instrument(skip(self))]
+    pub async fn set_daylight_saving(
+        &self,
+        device: &str,
+        dst: TimeRule,
+        std: TimeRule,
+    ) -> Result<()> {
+        self.execute(device, &TimeDst(Some(dst))).await?;
+        self.execute(device, &TimeStd(Some(std))).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_format() {
+        assert_eq!(Days::parse("-MTWTF-"), Some(Days::WEEKDAYS));
+        assert_eq!(Days::parse("1000001"), Some(Days::WEEKEND));
+        assert_eq!(Days::parse("0000000"), Some(Days::NONE));
+        assert_eq!(Days::parse("SMTWTF"), None);
+        assert_eq!(Days::WEEKDAYS.to_string(), "-MTWTF-");
+        assert_eq!((Days::SUNDAY | Days::WEDNESDAY).to_string(), "S--W---");
+        assert_eq!(Days::parse(&Days::ALL.to_string()), Some(Days::ALL));
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(parse_offset("07:30"), Some(450));
+        assert_eq!(parse_offset("+01:05"), Some(65));
+        assert_eq!(parse_offset("-00:45"), Some(-45));
+        assert_eq!(parse_offset("0730"), None);
+        assert_eq!(parse_offset("aa:bb"), None);
+        assert_eq!(format_offset(450), "+07:30");
+        assert_eq!(format_offset(-45), "-00:45");
+        assert_eq!(format_offset(0), "+00:00");
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("07:30"), Some((7, 30)));
+        assert_eq!(parse_time("00:00"), Some((0, 0)));
+        assert_eq!(parse_time("23:59"), Some((23, 59)));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("12:60"), None);
+        assert_eq!(parse_time("+07:30"), None);
+        assert_eq!(parse_time("-07:30"), None);
+        assert_eq!(parse_time("07:+3"), None);
+        assert_eq!(parse_time("0730"), None);
+    }
+
+    fn timer_info(json: &str) -> TimerInfo {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn timer_from_info() {
+        let timer = Timer::try_from(timer_info(
+            r#"{"Enable":1,"Mode":0,"Time":"07:30","Window":0,"Days":"-MTWTF-","Repeat":1,"Output":1,"Action":1}"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            timer,
+            Timer::daily(7, 30, Days::WEEKDAYS, 1, TimerAction::On)
+        );
+
+        let timer = Timer::try_from(timer_info(
+            r#"{"Enable":0,"Mode":2,"Time":"-00:45","Window":15,"Days":"1000001","Repeat":0,"Output":2,"Action":3}"#,
+        ))
+        .unwrap();
+        assert!(!timer.enabled);
+        assert_eq!(timer.schedule, TimerSchedule::Sunset { offset: -45 });
+        assert_eq!(timer.window, 15);
+        assert_eq!(timer.days, Days::WEEKEND);
+        assert!(!timer.repeat);
+        assert_eq!(timer.output, 2);
+        assert_eq!(timer.action, TimerAction::Rule);
+    }
+
+    #[test]
+    fn timer_without_mode() {
+        // firmware without sunrise and sunset support doesn't report the mode
+        let timer = Timer::try_from(timer_info(
+            r#"{"Enable":1,"Time":"22:00","Window":0,"Days":"SMTWTFS","Repeat":1,"Output":1,"Action":0}"#,
+        ))
+        .unwrap();
+        assert_eq!(timer, Timer::daily(22, 0, Days::ALL, 1, TimerAction::Off));
+    }
+
+    #[test]
+    fn reject_malformed_timer_info() {
+        let malformed = [
+            r#"{"Enable":1,"Mode":0,"Time":"25:00","Window":0,"Days":"SMTWTFS","Repeat":1,"Output":1,"Action":0}"#,
+            r#"{"Enable":1,"Mode":0,"Time":"-01:00","Window":0,"Days":"SMTWTFS","Repeat":1,"Output":1,"Action":0}"#,
+            r#"{"Enable":1,"Mode":3,"Time":"01:00","Window":0,"Days":"SMTWTFS","Repeat":1,"Output":1,"Action":0}"#,
+            r#"{"Enable":1,"Mode":0,"Time":"01:00","Window":0,"Days":"SMTWT","Repeat":1,"Output":1,"Action":0}"#,
+            r#"{"Enable":1,"Mode":0,"Time":"01:00","Window":0,"Days":"SMTWTFS","Repeat":1,"Output":1,"Action":4}"#,
+        ];
+        for json in malformed {
+            assert!(Timer::try_from(timer_info(json)).is_err(), "{json}");
+        }
+    }
+
+    #[test]
+    fn timer_json() {
+        let timer = Timer::daily(7, 5, Days::WEEKDAYS, 1, TimerAction::Toggle);
+        assert_eq!(
+            serde_json::from_str::<Value>(&timer.to_json()).unwrap(),
+            json!({"Enable":1,"Mode":0,"Time":"07:05","Window":0,"Days":"-MTWTF-","Repeat":1,"Output":1,"Action":2})
+        );
+    }
+
+    #[test]
+    fn timer_round_trip() {
+        let schedules = [
+            TimerSchedule::Time { hour: 0, minute: 0 },
+            TimerSchedule::Time {
+                hour: 23,
+                minute: 59,
+            },
+            TimerSchedule::Sunrise { offset: 0 },
+            TimerSchedule::Sunrise { offset: 90 },
+            TimerSchedule::Sunset { offset: -45 },
+        ];
+        for schedule in schedules {
+            let timer = Timer {
+                enabled: true,
+                schedule,
+                window: 5,
+                days: Days::SUNDAY | Days::FRIDAY,
+                repeat: false,
+                output: 3,
+                action: TimerAction::Off,
+            };
+            let info: TimerInfo = serde_json::from_str(&timer.to_json()).unwrap();
+            assert_eq!(Timer::try_from(info).unwrap(), timer);
+        }
+    }
+
+    #[test]
+    fn timezone() {
+        let parse = |json: &str| serde_json::from_str::<Timezone>(json).ok();
+        assert_eq!(parse("99"), Some(Timezone::DaylightSaving));
+        assert_eq!(parse("2"), Some(Timezone::Offset(120)));
+        assert_eq!(parse("-5"), Some(Timezone::Offset(-300)));
+        assert_eq!(parse("\"+05:30\""), Some(Timezone::Offset(330)));
+        assert_eq!(parse("\"-03:30\""), Some(Timezone::Offset(-210)));
+        assert_eq!(parse("\"later\""), None);
+        assert_eq!(parse("null"), None);
+    }
+}
@@ -0,0 +1,211 @@
+use std::fmt::{Display, Formatter};
+
+/// The prefixes used by tasmota to separate commands, command results and telemetry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TopicPrefix {
+    /// Commands send to the device, `cmnd` by default
+    Command,
+    /// Command results and status replies, `stat` by default
+    Stat,
+    /// Telemetry and the LWT, `tele` by default
+    Tele,
+}
+
+/// Layout of the mqtt topics used by a device
+///
+/// This mirrors the `FullTopic` and `Prefix1`-`Prefix3` settings of the device. The default
+/// is tasmota's default layout of `%prefix%/%topic%/` with the `cmnd`, `stat` and `tele` prefixes.
+///
+/// # Example
+///
+/// ```rust
+/// # use tasmota_mqtt_client::{TopicPrefix, TopicScheme};
+/// let scheme = TopicScheme::new("%topic%/%prefix%/", ["command", "status", "telemetry"]);
+/// assert_eq!(scheme.topic(TopicPrefix::Command, "tasmota_device", "Power"), "tasmota_device/command/Power");
+/// assert_eq!(
+///     scheme.parse("tasmota_device/telemetry/LWT"),
+///     Some((TopicPrefix::Tele, "tasmota_device", "LWT"))
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopicScheme {
+    full_topic: String,
+    prefixes: [String; 3],
+}
+
+impl Default for TopicScheme {
+    fn default() -> Self {
+        TopicScheme::new("%prefix%/%topic%/", ["cmnd", "stat", "tele"])
+    }
+}
+
+impl Display for TopicScheme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.full_topic, self.prefixes.join(", "))
+    }
+}
+
+impl TopicScheme {
+    /// Create a scheme from the full topic and the command, stat and tele prefixes
+    ///
+    /// Besides `%prefix%` and `%topic%`, the full topic can only contain placeholders that are already substituted.
+    pub fn new(full_topic: impl Into<String>, prefixes: [impl Into<String>; 3]) -> Self {
+        let mut full_topic = full_topic.into();
+        // tasmota always appends the command or message name as a new level
+        if !full_topic.ends_with('/') {
+            full_topic.push('/');
+        }
+        TopicScheme {
+            full_topic,
+            prefixes: prefixes.map(Into::into),
+        }
+    }
+
+    /// The full topic pattern
+    pub fn full_topic(&self) -> &str {
+        &self.full_topic
+    }
+
+    /// The string used for a prefix
+    pub fn prefix(&self, prefix: TopicPrefix) -> &str {
+        &self.prefixes[prefix as usize]
+    }
+
+    /// Build the topic for a message to or from a device
+    pub fn topic(&self, prefix: TopicPrefix, device: &str, name: &str) -> String {
+        let mut topic = self
+            .full_topic
+            .replace("%prefix%", self.prefix(prefix))
+            .replace("%topic%", device);
+        topic.push_str(name);
+        topic
+    }
+
+    /// Build a topic filter matching a message from all devices using this scheme
+    pub fn filter(&self, prefix: TopicPrefix, name: &str) -> String {
+        let mut filter: String = self
+            .levels()
+            .map(|level| {
+                let level = level.replace("%prefix%", self.prefix(prefix));
+                if level.contains('%') {
+                    "+/".into()
+                } else {
+                    format!("{level}/")
+                }
+            })
+            .collect();
+        filter.push_str(name);
+        filter
+    }
+
+    /// Split a topic into the prefix, device topic and message name
+    pub fn parse<'a>(&self, topic: &'a str) -> Option<(TopicPrefix, &'a str, &'a str)> {
+        let mut prefix = None;
+        let mut device = None;
+        let mut rest = topic;
+        for level in self.levels() {
+            let (value, remaining) = rest.split_once('/')?;
+            rest = remaining;
+            match placeholder(level) {
+                Some((before, "%prefix%", after)) => {
+                    let value = value.strip_prefix(before)?.strip_suffix(after)?;
+                    prefix = Some(self.parse_prefix(value)?);
+                }
+                Some((before, "%topic%", after)) => {
+                    device = Some(value.strip_prefix(before)?.strip_suffix(after)?);
+                }
+                _ if value != level => return None,
+                _ => {}
+            }
+        }
+        Some((prefix?, device?, rest))
+    }
+
+    fn parse_prefix(&self, value: &str) -> Option<TopicPrefix> {
+        [TopicPrefix::Command, TopicPrefix::Stat, TopicPrefix::Tele]
+            .into_iter()
+            .find(|prefix| self.prefix(*prefix) == value)
+    }
+
+    fn levels(&self) -> impl Iterator<Item = &str> {
+        self.full_topic.trim_end_matches('/').split('/')
+    }
+}
+
+/// Split a topic level like `tasmota_%topic%` into the text before, the placeholder and the text after it
+fn placeholder(level: &str) -> Option<(&str, &str, &str)> {
+    let start = level.find('%')?;
+    let end = start + 1 + level[start + 1..].find('%')?;
+    Some((&level[..start], &level[start..=end], &level[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_scheme() {
+        let scheme = TopicScheme::default();
+        assert_eq!(
+            scheme.topic(TopicPrefix::Command, "lamp", "Power"),
+            "cmnd/lamp/Power"
+        );
+        assert_eq!(scheme.filter(TopicPrefix::Tele, "LWT"), "tele/+/LWT");
+        assert_eq!(
+            scheme.parse("stat/lamp/RESULT"),
+            Some((TopicPrefix::Stat, "lamp", "RESULT"))
+        );
+    }
+
+    #[test]
+    fn topic_before_prefix() {
+        let scheme = TopicScheme::new("%topic%/%prefix%/", ["command", "status", "telemetry"]);
+        assert_eq!(
+            scheme.topic(TopicPrefix::Stat, "lamp", "POWER"),
+            "lamp/status/POWER"
+        );
+        assert_eq!(scheme.filter(TopicPrefix::Tele, "LWT"), "+/telemetry/LWT");
+        assert_eq!(
+            scheme.parse("lamp/telemetry/LWT"),
+            Some((TopicPrefix::Tele, "lamp", "LWT"))
+        );
+        assert_eq!(
+            scheme.parse("lamp/command/Power"),
+            Some((TopicPrefix::Command, "lamp", "Power"))
+        );
+    }
+
+    #[test]
+    fn fixed_levels_and_partial_placeholders() {
+        let scheme = TopicScheme::new("home/%prefix%/tasmota_%topic%", ["cmnd", "stat", "tele"]);
+        assert_eq!(scheme.full_topic(), "home/%prefix%/tasmota_%topic%/");
+        assert_eq!(
+            scheme.topic(TopicPrefix::Tele, "lamp", "STATE"),
+            "home/tele/tasmota_lamp/STATE"
+        );
+        // wildcards can only match full levels
+        assert_eq!(scheme.filter(TopicPrefix::Tele, "LWT"), "home/tele/+/LWT");
+        assert_eq!(
+            scheme.parse("home/tele/tasmota_lamp/LWT"),
+            Some((TopicPrefix::Tele, "lamp", "LWT"))
+        );
+    }
+
+    #[test]
+    fn parse_non_matching() {
+        let scheme = TopicScheme::new("home/%prefix%/tasmota_%topic%/", ["cmnd", "stat", "tele"]);
+        // unknown prefix
+        assert_eq!(scheme.parse("home/other/tasmota_lamp/LWT"), None);
+        // fixed level differs
+        assert_eq!(scheme.parse("office/tele/tasmota_lamp/LWT"), None);
+        // text around the placeholder differs
+        assert_eq!(scheme.parse("home/tele/lamp/LWT"), None);
+        // missing levels
+        assert_eq!(scheme.parse("home/tele/tasmota_lamp"), None);
+        assert_eq!(scheme.parse("home/tele"), None);
+
+        let scheme = TopicScheme::default();
+        assert_eq!(scheme.parse("tasmota/discovery/AABBCCDDEEFF/config"), None);
+        assert_eq!(scheme.parse("LWT"), None);
+    }
+}
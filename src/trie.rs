@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+/// Lookup table from mqtt topic filters to values, supporting the `+` and `#` wildcards
+///
+/// Finding the values for a topic only walks the levels of the topic, independent of the number of filters.
+pub struct TopicTrie<T> {
+    root: Node<T>,
+}
+
+struct Node<T> {
+    children: HashMap<String, Node<T>>,
+    values: Vec<(u64, T)>,
+}
+
+impl<T> Default for TopicTrie<T> {
+    fn default() -> Self {
+        TopicTrie {
+            root: Node::default(),
+        }
+    }
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            children: HashMap::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<T> TopicTrie<T> {
+    /// Add a value for a filter, the id is used to remove the value again
+    pub fn insert(&mut self, filter: &str, id: u64, value: T) {
+        let node = filter.split('/').fold(&mut self.root, |node, level| {
+            node.children.entry(level.into()).or_default()
+        });
+        node.values.push((id, value));
+    }
+
+    /// Remove a value for a filter
+    pub fn remove(&mut self, filter: &str, id: u64) -> Option<T> {
+        let levels: Vec<&str> = filter.split('/').collect();
+        self.root.remove(&levels, id)
+    }
+
+    /// Get all values with a filter that matches the topic
+    pub fn matches(&self, topic: &str) -> Vec<&T> {
+        let levels: Vec<&str> = topic.split('/').collect();
+        let mut found = Vec::new();
+        self.root.collect(&levels, &mut found);
+        found
+    }
+}
+
+impl<T> Node<T> {
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.values.is_empty()
+    }
+
+    fn remove(&mut self, levels: &[&str], id: u64) -> Option<T> {
+        match levels.split_first() {
+            None => {
+                let index = self
+                    .values
+                    .iter()
+                    .position(|(value_id, _)| *value_id == id)?;
+                Some(self.values.remove(index).1)
+            }
+            Some((level, rest)) => {
+                let child = self.children.get_mut(*level)?;
+                let removed = child.remove(rest, id);
+                if child.is_empty() {
+                    self.children.remove(*level);
+                }
+                removed
+            }
+        }
+    }
+
+    fn collect<'a>(&'a self, levels: &[&str], found: &mut Vec<&'a T>) {
+        // `#` also matches the parent level
+        if let Some(all) = self.children.get("#") {
+            found.extend(all.values.iter().map(|(_, value)| value));
+        }
+        match levels.split_first() {
+            None => found.extend(self.values.iter().map(|(_, value)| value)),
+            Some((level, rest)) => {
+                if let Some(child) = self.children.get(*level) {
+                    child.collect(rest, found);
+                }
+                if let Some(child) = self.children.get("+") {
+                    child.collect(rest, found);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(trie: &TopicTrie<&'static str>, topic: &str) -> Vec<&'static str> {
+        let mut found: Vec<_> = trie.matches(topic).into_iter().copied().collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn exact_match() {
+        let mut trie = TopicTrie::default();
+        trie.insert("stat/device/RESULT", 0, "result");
+        assert_eq!(matches(&trie, "stat/device/RESULT"), ["result"]);
+        assert!(matches(&trie, "stat/device").is_empty());
+        assert!(matches(&trie, "stat/device/RESULT/extra").is_empty());
+        assert!(matches(&trie, "stat/other/RESULT").is_empty());
+    }
+
+    #[test]
+    fn single_level_wildcard() {
+        let mut trie = TopicTrie::default();
+        trie.insert("tele/+/LWT", 0, "lwt");
+        trie.insert("stat/device/+", 1, "stat");
+        assert_eq!(matches(&trie, "tele/device/LWT"), ["lwt"]);
+        assert_eq!(matches(&trie, "tele/other/LWT"), ["lwt"]);
+        assert_eq!(matches(&trie, "stat/device/POWER"), ["stat"]);
+        assert!(matches(&trie, "tele/a/b/LWT").is_empty());
+        assert!(matches(&trie, "stat/device").is_empty());
+    }
+
+    #[test]
+    fn multi_level_wildcard() {
+        let mut trie = TopicTrie::default();
+        trie.insert("tasmota/discovery/#", 0, "discovery");
+        trie.insert("#", 1, "all");
+        assert_eq!(
+            matches(&trie, "tasmota/discovery/AABBCC/config"),
+            ["all", "discovery"]
+        );
+        // `#` includes the parent level
+        assert_eq!(matches(&trie, "tasmota/discovery"), ["all", "discovery"]);
+        assert_eq!(matches(&trie, "tasmota/other"), ["all"]);
+    }
+
+    #[test]
+    fn overlapping_filters() {
+        let mut trie = TopicTrie::default();
+        trie.insert("stat/device/RESULT", 0, "exact");
+        trie.insert("stat/device/+", 1, "plus");
+        trie.insert("stat/+/RESULT", 2, "device plus");
+        trie.insert("stat/#", 3, "hash");
+        trie.insert("stat/device/RESULT", 4, "exact again");
+        assert_eq!(
+            matches(&trie, "stat/device/RESULT"),
+            ["device plus", "exact", "exact again", "hash", "plus"]
+        );
+        assert_eq!(matches(&trie, "stat/device/POWER"), ["hash", "plus"]);
+        assert_eq!(matches(&trie, "stat/other/RESULT"), ["device plus", "hash"]);
+    }
+
+    #[test]
+    fn remove() {
+        let mut trie = TopicTrie::default();
+        trie.insert("stat/device/+", 0, "first");
+        trie.insert("stat/device/+", 1, "second");
+        trie.insert("stat/#", 2, "hash");
+
+        assert_eq!(trie.remove("stat/device/+", 0), Some("first"));
+        assert_eq!(matches(&trie, "stat/device/POWER"), ["hash", "second"]);
+        // removing twice or with the wrong filter does nothing
+        assert_eq!(trie.remove("stat/device/+", 0), None);
+        assert_eq!(trie.remove("stat/#", 1), None);
+        assert_eq!(trie.remove("stat/unknown/+", 1), None);
+
+        assert_eq!(trie.remove("stat/device/+", 1), Some("second"));
+        assert_eq!(trie.remove("stat/#", 2), Some("hash"));
+        assert!(matches(&trie, "stat/device/POWER").is_empty());
+        assert!(trie.root.is_empty());
+    }
+}
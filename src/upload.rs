@@ -0,0 +1,207 @@
+use crate::error::UploadError;
+use crate::mqtt::{MqttHelper, OverflowPolicy};
+use crate::{DeviceUpdate, DownloadedFile, Error, Result, TopicPrefix, TopicScheme};
+use async_stream::try_stream;
+use rumqttc::Publish;
+use serde::{Deserialize, Serialize};
+use std::pin::pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::select;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::{sleep_until, Instant};
+use tokio_stream::{Stream, StreamExt};
+use tracing::debug;
+
+/// Maximum number of bytes send in a single chunk, chosen to fit in the default mqtt buffer of the device
+const CHUNK_SIZE: usize = 700;
+
+/// The type of file being uploaded to the device
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum UploadType {
+    Firmware = 1,
+    Config = 2,
+}
+
+/// Progress of a file upload to a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadProgress {
+    /// Number of bytes sent to the device so far
+    pub sent: usize,
+    /// Total size of the file being uploaded
+    pub total: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct StartUploadPayload<'a> {
+    password: &'a str,
+    file: &'a str,
+    id: u32,
+    #[serde(rename = "Type")]
+    ty: u8,
+    size: u32,
+    md5: &'a str,
+    binary: u8,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct UploadResponse<'a> {
+    file_upload: Option<&'a str>,
+    id: Option<u32>,
+    md5: Option<&'a str>,
+}
+
+pub async fn upload_config(
+    mqtt: &MqttHelper,
+    topics: &TopicScheme,
+    client: &str,
+    password: &str,
+    file: &DownloadedFile,
+    timeout: Duration,
+    device_update: Receiver<DeviceUpdate>,
+) -> Result<()> {
+    let mut upload = pin!(upload_file(
+        mqtt,
+        topics,
+        client,
+        password,
+        file,
+        UploadType::Config,
+        timeout,
+        device_update
+    ));
+    while let Some(progress) = upload.next().await {
+        progress?;
+    }
+    Ok(())
+}
+
+/// Upload a file to the device, yielding the progress after every acknowledged chunk
+///
+/// The stream ends once the device has reported the upload as done,
+/// or errors if the device doesn't acknowledge a chunk within `timeout`.
+#[allow(clippy::too_many_arguments)]
+pub fn upload_file<'a>(
+    mqtt: &'a MqttHelper,
+    topics: &'a TopicScheme,
+    client: &'a str,
+    password: &'a str,
+    file: &'a DownloadedFile,
+    ty: UploadType,
+    timeout: Duration,
+    mut device_update: Receiver<DeviceUpdate>,
+) -> impl Stream<Item = Result<UploadProgress>> + 'a {
+    try_stream! {
+        let mut rx = mqtt
+            .subscribe(
+                topics.topic(TopicPrefix::Stat, client, "FILEUPLOAD"),
+                10,
+                OverflowPolicy::Disconnect,
+            )
+            .await?;
+        let topic = topics.topic(TopicPrefix::Command, client, "FILEUPLOAD");
+        let chunk_topic = topics.topic(TopicPrefix::Command, client, "FILEUPLOAD201");
+
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs() as u32)
+            .unwrap_or_default();
+        let md5 = hex::encode(file.md5);
+        let total = file.data.len();
+
+        mqtt.send(
+            &topic,
+            &StartUploadPayload {
+                password,
+                file: &file.name,
+                id,
+                ty: ty as u8,
+                size: total as u32,
+                md5: &md5,
+                binary: 1,
+            },
+        )
+        .await?;
+
+        let mut chunks = file.data.chunks(CHUNK_SIZE);
+        let mut sent = 0;
+        let mut reported = None;
+        let mut deadline = Instant::now() + timeout;
+
+        loop {
+            let msg: Result<Publish> = select! {
+                msg = rx.recv() => {
+                    msg.map_err(Error::from)
+                }
+                discovery = device_update.recv() => {
+                    match discovery {
+                        Ok(DeviceUpdate::Removed(device)) if device.as_str() == client => {
+                            Err(UploadError::Gone.into())
+                        }
+                        _ => continue,
+                    }
+                }
+                _ = sleep_until(deadline) => {
+                    Err(Error::Timeout)
+                }
+            };
+            let msg = msg?;
+
+            let Ok(response) = serde_json::from_slice::<UploadResponse>(msg.payload.as_ref()) else {
+                continue;
+            };
+            debug!(message = ?response, "processing upload status message");
+            if let Some(device_md5) = response.md5 {
+                let mut device_hash = [0; 16];
+                hex::decode_to_slice(device_md5, &mut device_hash[..]).map_err(UploadError::from)?;
+                if device_hash != file.md5 {
+                    Err(UploadError::MismatchedHash(file.md5, device_hash))?;
+                }
+            }
+            if let Some(status) = response.file_upload {
+                match status {
+                    "Aborted" => {
+                        Err(UploadError::UploadAborted)?;
+                    }
+                    "Error 1" => {
+                        Err(UploadError::InvalidPassword)?;
+                    }
+                    "Error 2" => {
+                        Err(UploadError::BadChunkSize)?;
+                    }
+                    "Error 3" => {
+                        Err(UploadError::InvalidFileType)?;
+                    }
+                    "Done" => {
+                        break;
+                    }
+                    _ if status.starts_with("Error") => {
+                        Err(UploadError::Unknown(status.into()))?;
+                    }
+                    _ => {}
+                }
+            } else if response.id != Some(id) {
+                // not an acknowledgement for our upload
+                continue;
+            } else {
+                // the device acknowledged the previous chunk
+                yield UploadProgress { sent, total };
+                reported = Some(sent);
+            }
+
+            if let Some(chunk) = chunks.next() {
+                debug!(size = chunk.len(), "sending upload chunk");
+                mqtt.send_bytes(&chunk_topic, chunk.to_vec()).await?;
+                sent += chunk.len();
+                deadline = Instant::now() + timeout;
+            }
+        }
+
+        // the device might report the upload as done without acknowledging the last chunk
+        if reported != Some(sent) {
+            yield UploadProgress { sent, total };
+        }
+    }
+}